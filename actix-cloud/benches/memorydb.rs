@@ -0,0 +1,55 @@
+//! Throughput of `DefaultBackend` with a single shard versus the default sharding.
+//!
+//! Run with `cargo bench --bench memorydb`.
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use actix_cloud::memorydb::{default::DefaultBackend, MemoryDB};
+
+const TASKS: usize = 64;
+const OPS: usize = 20000;
+const KEYS: usize = 10000;
+
+/// Run `TASKS` concurrent workers, every `write`-th operation is a write. Return operations per second.
+async fn run(db: Arc<DefaultBackend>, write: usize) -> f64 {
+    let start = Instant::now();
+    let handles: Vec<_> = (0..TASKS)
+        .map(|t| {
+            let db = db.clone();
+            tokio::spawn(async move {
+                for i in 0..OPS {
+                    let key = format!("key{}", (t * OPS + i * 7) % KEYS);
+                    if i % write == 0 {
+                        db.set_ex(&key, "value", &Duration::from_secs(60))
+                            .await
+                            .unwrap();
+                    } else {
+                        db.get(&key).await.unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+    for i in handles {
+        i.await.unwrap();
+    }
+    (TASKS * OPS) as f64 / start.elapsed().as_secs_f64()
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    for (name, write) in [("write only", 1), ("25% write", 4)] {
+        for (shards, db) in [
+            (
+                "1 shard",
+                DefaultBackend::builder().shards(1).build().unwrap(),
+            ),
+            ("default", DefaultBackend::new(None)),
+        ] {
+            let ops = run(Arc::new(db), write).await;
+            println!("{name:>10} {shards:>8}: {ops:>12.0} ops/s");
+        }
+    }
+}
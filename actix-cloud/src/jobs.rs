@@ -0,0 +1,669 @@
+//! Persistent background jobs on top of [`MemoryDB`].
+//!
+//! Jobs are stored in the database, so they survive the crash of a single node.
+//! Each job is delivered at least once:
+//! - A claimed job is hidden for the visibility timeout, which is renewed while the handler runs.
+//!   If the worker crashes, the job is delivered again after the timeout.
+//! - A failed job is retried with backoff until `max_attempts` is reached,
+//!   then it is moved to the dead letters.
+//!
+//! ```no_run
+//! # async fn f(state: actix_cloud::state::GlobalState) -> actix_cloud::Result<()> {
+//! use actix_cloud::jobs::{Queue, Worker};
+//!
+//! let queue = Queue::new(state.memorydb.clone(), "jobs");
+//! queue.enqueue("email", &"user@example.com").await?;
+//!
+//! let worker = Worker::new(queue)
+//!     .handler("email", |job| async move {
+//!         let to: String = job.payload()?;
+//!         // Send email.
+//!         Ok(())
+//!     })
+//!     .start();
+//! worker.stop_on(&state.server);
+//! # Ok(())
+//! # }
+//! ```
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use futures::future::BoxFuture;
+use rand::distr::{Alphanumeric, SampleString as _};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    sync::watch,
+    task::JoinHandle,
+    time::{interval, sleep},
+};
+use tracing::warn;
+
+use crate::{
+    memorydb::{
+        clock::{Clock, SystemClock},
+        lock::Lock,
+        MemoryDB,
+    },
+    Result,
+};
+
+/// Number of visible jobs fetched by each claim.
+///
+/// Visible jobs are only locked while being claimed by other workers, so a small window is enough.
+const CLAIM_WINDOW: usize = 16;
+
+/// Job stored in the queue.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    /// Selects the handler of the job.
+    pub kind: String,
+    pub payload: serde_json::Value,
+    /// Number of deliveries, including the current one.
+    pub attempts: u32,
+    /// Error of the last failed attempt.
+    pub error: Option<String>,
+}
+
+impl Job {
+    /// Deserialize the payload.
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_value(self.payload.clone()).map_err(Into::into)
+    }
+}
+
+/// Delay before retrying a failed job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    Fixed(Duration),
+    /// `base * 2^(attempts - 1)`, at most `max`.
+    Exponential {
+        base: Duration,
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// Delay after the failure of the `attempts`-th delivery.
+    pub fn delay(&self, attempts: u32) -> Duration {
+        match self {
+            Self::Fixed(x) => *x,
+            Self::Exponential { base, max } => {
+                let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+                base.saturating_mul(factor).min(*max)
+            }
+        }
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::Exponential {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Job queue stored in [`MemoryDB`].
+///
+/// Keys used by the queue `name`:
+/// - `{name}:queue`: sorted set of job IDs, scored by the time they become visible.
+/// - `{name}:jobs`: hash of pending jobs.
+/// - `{name}:dead`: hash of dead letters.
+/// - `{name}:lock:{id}`: lock of a claimed job.
+#[derive(Clone)]
+pub struct Queue {
+    db: Arc<dyn MemoryDB>,
+    name: String,
+    max_attempts: u32,
+    backoff: Backoff,
+    visibility_timeout: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl Queue {
+    pub fn new<S>(db: Arc<dyn MemoryDB>, name: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            db,
+            name: name.into(),
+            max_attempts: 5,
+            backoff: Backoff::default(),
+            visibility_timeout: Duration::from_secs(30),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Maximum deliveries of a job before it is moved to the dead letters. Default is 5.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Delay before retrying a failed job. Default is exponential from 1 second to 5 minutes.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// How long a claimed job is hidden from other workers, at least 1 second. Default is 30 seconds.
+    ///
+    /// The timeout is renewed while the handler runs, it only expires if the worker is gone.
+    pub fn visibility_timeout(mut self, timeout: Duration) -> Self {
+        self.visibility_timeout = timeout.max(Duration::from_secs(1));
+        self
+    }
+
+    /// Time source of delays, should be the same as the clock of the database.
+    pub fn clock<C>(mut self, clock: C) -> Self
+    where
+        C: Clock + 'static,
+    {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}:{}", self.name, key)
+    }
+
+    fn lock_key(&self, id: &str) -> String {
+        format!("{}:lock:{}", self.name, id)
+    }
+
+    fn after(&self, delay: Duration) -> f64 {
+        let delay: i64 = delay.as_millis().try_into().unwrap_or(i64::MAX);
+        self.clock.now().saturating_add(delay) as f64
+    }
+
+    /// Enqueue a job of `kind`, return its ID.
+    pub async fn enqueue<T>(&self, kind: &str, payload: &T) -> Result<String>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.enqueue_in(kind, payload, Duration::ZERO).await
+    }
+
+    /// Enqueue a job of `kind` to run after `delay`, return its ID.
+    pub async fn enqueue_in<T>(&self, kind: &str, payload: &T, delay: Duration) -> Result<String>
+    where
+        T: Serialize + ?Sized,
+    {
+        let job = Job {
+            id: Alphanumeric.sample_string(&mut rand::rng(), 32),
+            kind: kind.to_owned(),
+            payload: serde_json::to_value(payload)?,
+            attempts: 0,
+            error: None,
+        };
+        // The job must be stored before it is visible.
+        self.db
+            .hset(&self.key("jobs"), &job.id, &serde_json::to_string(&job)?)
+            .await?;
+        self.db
+            .zadd(&self.key("queue"), &job.id, self.after(delay))
+            .await?;
+        Ok(job.id)
+    }
+
+    /// Number of pending jobs, including delayed and running ones.
+    pub async fn len(&self) -> Result<u64> {
+        self.db.zcard(&self.key("queue")).await
+    }
+
+    pub async fn is_empty(&self) -> Result<bool> {
+        Ok(self.len().await? == 0)
+    }
+
+    /// Jobs that failed `max_attempts` times.
+    pub async fn dead(&self) -> Result<Vec<Job>> {
+        self.db
+            .hgetall(&self.key("dead"))
+            .await?
+            .values()
+            .map(|x| serde_json::from_str(x).map_err(Into::into))
+            .collect()
+    }
+
+    /// Enqueue the dead letter `id` again with attempts reset.
+    /// Return `false` if it does not exist.
+    pub async fn retry_dead(&self, id: &str) -> Result<bool> {
+        let Some(job) = self.db.hget(&self.key("dead"), id).await? else {
+            return Ok(false);
+        };
+        let mut job: Job = serde_json::from_str(&job)?;
+        job.attempts = 0;
+        job.error = None;
+        self.db
+            .hset(&self.key("jobs"), id, &serde_json::to_string(&job)?)
+            .await?;
+        self.db
+            .zadd(&self.key("queue"), id, self.after(Duration::ZERO))
+            .await?;
+        self.db.hdel(&self.key("dead"), id).await
+    }
+
+    /// Remove all dead letters.
+    pub async fn clear_dead(&self) -> Result<()> {
+        self.db.del(&self.key("dead")).await?;
+        Ok(())
+    }
+
+    /// Claim a visible job and hide it for the visibility timeout.
+    async fn claim(&self) -> Result<Option<Claim>> {
+        let queue = self.key("queue");
+        let visible = self
+            .db
+            .zrange_by_score_limit(
+                &queue,
+                f64::NEG_INFINITY,
+                self.after(Duration::ZERO),
+                0,
+                CLAIM_WINDOW,
+            )
+            .await?;
+        for (id, _) in visible {
+            // Jobs whose lock is held are claimed by other workers.
+            let Some(lock) =
+                Lock::try_acquire(self.db.clone(), self.lock_key(&id), self.visibility_timeout)
+                    .await?
+            else {
+                continue;
+            };
+            let Some(job) = self.db.hget(&self.key("jobs"), &id).await? else {
+                // Acknowledged by others.
+                self.db.zrem(&queue, &id).await?;
+                lock.release().await?;
+                continue;
+            };
+            self.db
+                .zadd(&queue, &id, self.after(self.visibility_timeout))
+                .await?;
+
+            let mut job: Job = serde_json::from_str(&job)?;
+            job.attempts += 1;
+            let claim = Claim { job, lock };
+            if claim.job.attempts > self.max_attempts {
+                // The worker crashed during the last attempt.
+                self.bury(claim).await?;
+                continue;
+            }
+            self.db
+                .hset(&self.key("jobs"), &id, &serde_json::to_string(&claim.job)?)
+                .await?;
+            return Ok(Some(claim));
+        }
+        Ok(None)
+    }
+
+    /// Extend the visibility timeout. Return `false` if the claim is lost.
+    async fn renew(&self, claim: &Claim) -> Result<bool> {
+        if !claim.lock.renew().await? {
+            return Ok(false);
+        }
+        self.db
+            .zadd(
+                &self.key("queue"),
+                &claim.job.id,
+                self.after(self.visibility_timeout),
+            )
+            .await?;
+        Ok(true)
+    }
+
+    /// Remove the finished job.
+    async fn ack(&self, claim: Claim) -> Result<()> {
+        // Removed from the hash first, a dangling ID is skipped by `claim`.
+        self.db.hdel(&self.key("jobs"), &claim.job.id).await?;
+        self.db.zrem(&self.key("queue"), &claim.job.id).await?;
+        claim.lock.release().await?;
+        Ok(())
+    }
+
+    /// Retry the failed job with backoff, or move it to the dead letters.
+    async fn fail(&self, mut claim: Claim, error: String) -> Result<()> {
+        claim.job.error = Some(error);
+        if claim.job.attempts >= self.max_attempts {
+            return self.bury(claim).await;
+        }
+        self.db
+            .hset(
+                &self.key("jobs"),
+                &claim.job.id,
+                &serde_json::to_string(&claim.job)?,
+            )
+            .await?;
+        self.db
+            .zadd(
+                &self.key("queue"),
+                &claim.job.id,
+                self.after(self.backoff.delay(claim.job.attempts)),
+            )
+            .await?;
+        claim.lock.release().await?;
+        Ok(())
+    }
+
+    /// Move the job to the dead letters.
+    async fn bury(&self, claim: Claim) -> Result<()> {
+        self.db
+            .hset(
+                &self.key("dead"),
+                &claim.job.id,
+                &serde_json::to_string(&claim.job)?,
+            )
+            .await?;
+        self.ack(claim).await
+    }
+}
+
+/// Job claimed by a worker.
+struct Claim {
+    job: Job,
+    lock: Lock,
+}
+
+type Handler = Arc<dyn Fn(Job) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// Worker processing jobs of a [`Queue`].
+pub struct Worker {
+    queue: Queue,
+    handlers: HashMap<String, Handler>,
+    concurrency: usize,
+    poll_interval: Duration,
+}
+
+impl Worker {
+    pub fn new(queue: Queue) -> Self {
+        Self {
+            queue,
+            handlers: HashMap::new(),
+            concurrency: 1,
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Handle jobs of `kind`. Jobs without a handler fail.
+    pub fn handler<F, Fut>(mut self, kind: &str, f: F) -> Self
+    where
+        F: Fn(Job) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.handlers
+            .insert(kind.to_owned(), Arc::new(move |job| Box::pin(f(job))));
+        self
+    }
+
+    /// Number of jobs processed at the same time. Default is 1.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Wait time when no job is visible. Default is 1 second.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Spawn the worker on the current runtime.
+    ///
+    /// # Panics
+    /// Panics if it is not called inside a tokio runtime, such as the actix runtime.
+    pub fn start(self) -> WorkerHandle {
+        let (tx, rx) = watch::channel(false);
+        let worker = Arc::new(self);
+        let tasks = (0..worker.concurrency)
+            .map(|_| tokio::spawn(worker.clone().run(rx.clone())))
+            .collect();
+        WorkerHandle {
+            stop: Arc::new(tx),
+            tasks,
+        }
+    }
+
+    async fn run(self: Arc<Self>, mut stop: watch::Receiver<bool>) {
+        while !*stop.borrow() {
+            let claim = match self.queue.claim().await {
+                Ok(Some(x)) => x,
+                x => {
+                    if let Err(e) = x {
+                        warn!(queue = self.queue.name, error = %e, "Failed to claim job");
+                    }
+                    tokio::select! {
+                        _ = sleep(self.poll_interval) => {}
+                        _ = stop.changed() => {}
+                    }
+                    continue;
+                }
+            };
+            // Errors are retried by the visibility timeout.
+            let id = claim.job.id.clone();
+            let ret = match self.process(&claim).await {
+                Ok(()) => self.queue.ack(claim).await,
+                Err(e) => self.queue.fail(claim, e.to_string()).await,
+            };
+            if let Err(e) = ret {
+                warn!(queue = self.queue.name, id, error = %e, "Failed to finish job");
+            }
+        }
+    }
+
+    /// Run the handler and renew the claim until it finishes.
+    async fn process(&self, claim: &Claim) -> Result<()> {
+        let Some(handler) = self.handlers.get(&claim.job.kind) else {
+            return Err(anyhow!("No handler for job kind `{}`", claim.job.kind));
+        };
+        // Spawned so that a panic only fails the job.
+        let mut task = tokio::spawn(handler(claim.job.clone()));
+        let mut renew = interval(self.queue.visibility_timeout / 3);
+        renew.tick().await;
+        loop {
+            tokio::select! {
+                x = &mut task => return x.map_err(|e| anyhow!("Job panicked: {e}"))?,
+                _ = renew.tick() => {
+                    if let Err(e) = self.queue.renew(claim).await {
+                        warn!(
+                            queue = self.queue.name,
+                            id = claim.job.id,
+                            error = %e,
+                            "Failed to renew job"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Handle of a started [`Worker`].
+pub struct WorkerHandle {
+    stop: Arc<watch::Sender<bool>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl WorkerHandle {
+    /// Stop claiming new jobs, running jobs are finished.
+    pub fn stop(&self) {
+        let _ = self.stop.send(true);
+    }
+
+    /// Stop the worker when `server` is stopped.
+    #[cfg(feature = "state")]
+    pub fn stop_on(&self, server: &crate::state::ServerHandle) {
+        let stop = self.stop.clone();
+        server.on_stop(move || {
+            let _ = stop.send(true);
+        });
+    }
+
+    /// Wait until the worker is stopped and running jobs are finished.
+    pub async fn join(self) {
+        for i in self.tasks {
+            let _ = i.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::memorydb::{clock::MockClock, default::DefaultBackend};
+
+    fn setup() -> (Queue, MockClock) {
+        let clock = MockClock::new();
+        let db = DefaultBackend::builder()
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        let queue = Queue::new(Arc::new(db), "jobs")
+            .max_attempts(2)
+            .backoff(Backoff::Fixed(Duration::from_secs(10)))
+            .visibility_timeout(Duration::from_secs(30))
+            .clock(clock.clone());
+        (queue, clock)
+    }
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Backoff::default();
+        assert_eq!(backoff.delay(1), Duration::from_secs(1));
+        assert_eq!(backoff.delay(3), Duration::from_secs(4));
+        assert_eq!(backoff.delay(100), Duration::from_secs(300));
+    }
+
+    #[tokio::test]
+    async fn test_queue() {
+        let (queue, clock) = setup();
+        let id = queue.enqueue("kind", &1).await.unwrap();
+        let delayed = queue
+            .enqueue_in("kind", &2, Duration::from_secs(90))
+            .await
+            .unwrap();
+        assert_eq!(queue.len().await.unwrap(), 2);
+
+        // Hidden after claimed, visible again after the visibility timeout.
+        let claim = queue.claim().await.unwrap().unwrap();
+        assert_eq!(claim.job.id, id);
+        assert_eq!(claim.job.attempts, 1);
+        assert_eq!(claim.job.payload::<i32>().unwrap(), 1);
+        assert!(queue.claim().await.unwrap().is_none());
+        clock.advance(Duration::from_secs(30));
+        let claim = queue.claim().await.unwrap().unwrap();
+        assert_eq!(claim.job.id, id);
+        assert_eq!(claim.job.attempts, 2);
+
+        // Crashed during the last attempt.
+        clock.advance(Duration::from_secs(30));
+        assert!(queue.claim().await.unwrap().is_none());
+        let dead = queue.dead().await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].id, id);
+        clock.advance(Duration::from_secs(30));
+        let claim = queue.claim().await.unwrap().unwrap();
+        assert_eq!(claim.job.id, delayed);
+
+        // Retry with backoff.
+        queue.fail(claim, "error".to_owned()).await.unwrap();
+        assert!(queue.claim().await.unwrap().is_none());
+        clock.advance(Duration::from_secs(10));
+        let claim = queue.claim().await.unwrap().unwrap();
+        assert_eq!(claim.job.error.as_deref(), Some("error"));
+        assert!(queue.renew(&claim).await.unwrap());
+        queue.ack(claim).await.unwrap();
+        assert!(queue.is_empty().await.unwrap());
+
+        assert!(queue.retry_dead(&id).await.unwrap());
+        assert!(!queue.retry_dead(&id).await.unwrap());
+        assert!(queue.dead().await.unwrap().is_empty());
+        let claim = queue.claim().await.unwrap().unwrap();
+        assert_eq!(
+            (claim.job.id.as_str(), claim.job.attempts),
+            (id.as_str(), 1)
+        );
+        queue.fail(claim, "error".to_owned()).await.unwrap();
+        clock.advance(Duration::from_secs(10));
+        let claim = queue.claim().await.unwrap().unwrap();
+        queue.fail(claim, "error".to_owned()).await.unwrap();
+        assert!(queue.is_empty().await.unwrap());
+        assert_eq!(queue.dead().await.unwrap().len(), 1);
+        queue.clear_dead().await.unwrap();
+        assert!(queue.dead().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_claim_window() {
+        let (queue, _) = setup();
+        for i in 0..CLAIM_WINDOW * 2 {
+            queue.enqueue("kind", &i).await.unwrap();
+        }
+        for _ in 0..CLAIM_WINDOW * 2 {
+            let claim = queue.claim().await.unwrap().unwrap();
+            queue.ack(claim).await.unwrap();
+        }
+        assert!(queue.claim().await.unwrap().is_none());
+        assert!(queue.is_empty().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_worker() {
+        let (queue, _) = setup();
+        let queue = queue.backoff(Backoff::Fixed(Duration::ZERO));
+        let count = Arc::new(AtomicU32::new(0));
+        for i in 0..10 {
+            queue.enqueue("add", &i).await.unwrap();
+        }
+        queue.enqueue("fail", &()).await.unwrap();
+        queue.enqueue("panic", &()).await.unwrap();
+        queue.enqueue("unknown", &()).await.unwrap();
+
+        let handle = Worker::new(queue.clone())
+            .handler("add", {
+                let count = count.clone();
+                move |job| {
+                    let count = count.clone();
+                    async move {
+                        count.fetch_add(job.payload()?, Ordering::Relaxed);
+                        Ok(())
+                    }
+                }
+            })
+            .handler("fail", |_| async { Err(anyhow!("error")) })
+            .handler("panic", |_| async { panic!("error") })
+            .concurrency(2)
+            .poll_interval(Duration::from_millis(10))
+            .start();
+        while !queue.is_empty().await.unwrap() {
+            sleep(Duration::from_millis(10)).await;
+        }
+        handle.stop();
+        handle.join().await;
+
+        assert_eq!(count.load(Ordering::Relaxed), 45);
+        let mut dead: Vec<_> = queue
+            .dead()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| (x.kind, x.attempts))
+            .collect();
+        dead.sort();
+        assert_eq!(
+            dead,
+            [
+                ("fail".to_owned(), 2),
+                ("panic".to_owned(), 2),
+                ("unknown".to_owned(), 2)
+            ]
+        );
+    }
+}
@@ -36,6 +36,8 @@ pub use tracing;
 pub mod csrf;
 #[cfg(feature = "i18n")]
 pub mod i18n;
+#[cfg(feature = "jobs")]
+pub mod jobs;
 #[cfg(feature = "logger")]
 pub mod logger;
 #[cfg(feature = "memorydb")]
@@ -173,6 +173,12 @@ pub struct LoggerBuilder {
     handler: Option<HandlerFn>,
 }
 
+impl Default for LoggerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl LoggerBuilder {
     /// Return colored string of `level`.
     ///
@@ -0,0 +1,84 @@
+use std::{
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::Utc;
+
+/// Time source used to expire keys.
+pub trait Clock: Send + Sync {
+    /// Current Unix timestamp in milliseconds.
+    fn now(&self) -> i64;
+}
+
+/// Wall clock time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        Utc::now().timestamp_millis()
+    }
+}
+
+/// Manually advanced clock for deterministic tests.
+///
+/// Clones share the same time, so a clone can be passed to the backend and the other one advanced.
+///
+/// ```
+/// use std::time::Duration;
+/// use actix_cloud::memorydb::{clock::MockClock, default::DefaultBackend, MemoryDB};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let clock = MockClock::new();
+/// let db = DefaultBackend::builder().clock(clock.clone()).build().unwrap();
+/// db.set_ex("key", "value", &Duration::from_secs(60)).await.unwrap();
+/// clock.advance(Duration::from_secs(60));
+/// assert_eq!(db.get("key").await.unwrap(), None);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockClock(Arc<AtomicI64>);
+
+impl MockClock {
+    /// Start from the current wall clock time.
+    pub fn new() -> Self {
+        Self::at(SystemClock.now())
+    }
+
+    /// Start from the Unix timestamp `now` in milliseconds.
+    pub fn at(now: i64) -> Self {
+        Self(Arc::new(AtomicI64::new(now)))
+    }
+
+    /// Set the current Unix timestamp in milliseconds.
+    pub fn set(&self, now: i64) {
+        self.0.store(now, Ordering::Relaxed);
+    }
+
+    /// Move the time forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let x = duration.as_millis().try_into().unwrap_or(i64::MAX);
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |now| {
+                Some(now.saturating_add(x))
+            });
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
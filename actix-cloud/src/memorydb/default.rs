@@ -1,100 +1,1022 @@
 use std::{
-    cmp::{max, Reverse},
-    collections::HashMap,
-    sync::Arc,
+    cmp::max,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    hash::{BuildHasher, RandomState},
+    mem::size_of,
+    num::NonZeroUsize,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
     time::Duration,
 };
 
-use anyhow::bail;
+#[cfg(feature = "persist")]
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail};
 use async_trait::async_trait;
-use chrono::Utc;
+use futures::{channel::mpsc, stream, StreamExt};
 use glob::Pattern;
-use parking_lot::{RwLock, RwLockWriteGuard};
-use priority_queue::PriorityQueue;
+use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
 
-use super::interface::MemoryDB;
+use super::clock::{Clock, SystemClock};
+use super::interface::{
+    Event, EventKind, EventStream, KeyStream, MemoryDB, Message, MessageStream, Op, Reply,
+    Transaction,
+};
+#[cfg(feature = "persist")]
+use super::persist::{Persister, Record};
 use crate::Result;
 
-struct Data(String, Option<i64>);
+pub(super) const WRONG_TYPE: &str = "Operation against a key holding the wrong kind of value";
+pub(super) const NAN_SCORE: &str = "Score is not a number";
+
+#[cfg_attr(feature = "persist", derive(serde::Serialize, serde::Deserialize))]
+pub(super) enum Value {
+    /// Binary-safe string.
+    String(#[cfg_attr(feature = "persist", serde(with = "super::persist::bytes"))] Vec<u8>),
+    Hash(HashMap<String, String>),
+    SortedSet(#[cfg_attr(feature = "persist", serde(with = "super::persist::zset"))] ZSet),
+}
+
+impl Value {
+    fn as_string(&self) -> Result<&Vec<u8>> {
+        match self {
+            Value::String(x) => Ok(x),
+            _ => bail!(WRONG_TYPE),
+        }
+    }
+
+    fn as_string_mut(&mut self) -> Result<&mut Vec<u8>> {
+        match self {
+            Value::String(x) => Ok(x),
+            _ => bail!(WRONG_TYPE),
+        }
+    }
+
+    fn as_hash(&self) -> Result<&HashMap<String, String>> {
+        match self {
+            Value::Hash(x) => Ok(x),
+            _ => bail!(WRONG_TYPE),
+        }
+    }
+
+    fn as_hash_mut(&mut self) -> Result<&mut HashMap<String, String>> {
+        match self {
+            Value::Hash(x) => Ok(x),
+            _ => bail!(WRONG_TYPE),
+        }
+    }
+
+    fn as_zset(&self) -> Result<&ZSet> {
+        match self {
+            Value::SortedSet(x) => Ok(x),
+            _ => bail!(WRONG_TYPE),
+        }
+    }
+
+    fn as_zset_mut(&mut self) -> Result<&mut ZSet> {
+        match self {
+            Value::SortedSet(x) => Ok(x),
+            _ => bail!(WRONG_TYPE),
+        }
+    }
+}
+
+/// Score with total order, NaN is rejected before insertion.
+#[derive(Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Sorted set ordered by score then by member.
+#[derive(Default)]
+pub(super) struct ZSet {
+    scores: HashMap<String, f64>,
+    members: BTreeSet<(Score, String)>,
+}
+
+impl ZSet {
+    /// Add `member` or update its score. Return `true` if `member` is newly added.
+    pub(super) fn insert(&mut self, member: &str, score: f64) -> bool {
+        // Normalise -0.0, which is equal to 0.0.
+        let score = score + 0.0;
+        let ret = match self.scores.insert(member.to_owned(), score) {
+            Some(x) => {
+                self.members.remove(&(Score(x), member.to_owned()));
+                false
+            }
+            None => true,
+        };
+        self.members.insert((Score(score), member.to_owned()));
+        ret
+    }
+
+    fn remove(&mut self, member: &str) -> bool {
+        match self.scores.remove(member) {
+            Some(x) => self.members.remove(&(Score(x), member.to_owned())),
+            None => false,
+        }
+    }
+
+    /// Members with score in `[min, max]` in order.
+    fn range(&self, min: f64, max: f64) -> impl Iterator<Item = &(Score, String)> {
+        self.members
+            .range((Score(min), String::new())..)
+            .take_while(move |(x, _)| x.0 <= max)
+    }
+
+    fn remove_range(&mut self, min: f64, max: f64) -> u64 {
+        let removed: Vec<_> = self.range(min, max).cloned().collect();
+        for (x, member) in &removed {
+            self.scores.remove(member);
+            self.members.remove(&(*x, member.to_owned()));
+        }
+        removed.len() as u64
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Members and their scores in arbitrary order.
+    pub(super) fn iter(&self) -> impl Iterator<Item = (&String, f64)> {
+        self.scores.iter().map(|(k, v)| (k, *v))
+    }
+}
+
+/// Access statistics used by eviction policies.
+#[derive(Default)]
+struct Access {
+    /// Last access time in milliseconds.
+    time: AtomicI64,
+    /// Access count.
+    hits: AtomicU64,
+}
+
+impl Access {
+    fn new(now: i64) -> Self {
+        Self {
+            time: AtomicI64::new(now),
+            hits: AtomicU64::new(1),
+        }
+    }
+
+    fn touch(&self, now: i64) {
+        self.time.store(now, Ordering::Relaxed);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Value, its absolute expiry time in milliseconds, access statistics and memory usage.
+///
+/// `now` is the current time in milliseconds given by the [`Clock`] of the backend.
+#[cfg_attr(feature = "persist", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct Data(
+    Value,
+    Option<i64>,
+    #[cfg_attr(feature = "persist", serde(skip))] Access,
+    #[cfg_attr(feature = "persist", serde(skip))] usize,
+);
 
 impl Data {
-    fn now() -> i64 {
-        Utc::now().timestamp()
+    fn parse_ttl(ttl: Option<i64>, now: i64) -> Option<i64> {
+        ttl.map(|x| now.saturating_add(x))
+    }
+
+    fn new(value: Value, ttl: Option<i64>, now: i64) -> Self {
+        Self(value, Self::parse_ttl(ttl, now), Access::new(now), 0)
+    }
+
+    fn set_ttl(&mut self, ttl: Option<i64>, now: i64) {
+        self.1 = Self::parse_ttl(ttl, now);
+    }
+
+    fn get_ttl(&self, now: i64) -> Option<i64> {
+        self.1.map(|x| x.saturating_sub(now))
+    }
+
+    pub(super) fn valid(&self, now: i64) -> bool {
+        if let Some(x) = self.1 {
+            x > now
+        } else {
+            true
+        }
+    }
+}
+
+/// Usage shared by all shards.
+#[derive(Default)]
+struct Usage {
+    keys: AtomicUsize,
+    memory: AtomicUsize,
+}
+
+type Watcher = (Pattern, mpsc::Sender<Event>);
+
+/// Watchers of key changes shared by all shards.
+#[derive(Default)]
+struct Watchers {
+    /// Number of watchers, checked without locking so that writes are cheap when nobody watches.
+    len: AtomicUsize,
+    list: Mutex<Vec<Watcher>>,
+}
+
+impl Watchers {
+    fn add(&self, watcher: Watcher) {
+        let mut list = self.list.lock();
+        list.push(watcher);
+        self.len.store(list.len(), Ordering::Release);
+    }
+
+    /// Send an event to the watchers of `key`.
+    ///
+    /// Events are dropped for watchers more than [`WATCH_BUFFER`] events behind.
+    fn notify(&self, key: &str, kind: EventKind) {
+        if self.len.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let mut list = self.list.lock();
+        list.retain_mut(|(p, tx)| {
+            if !p.matches(key) {
+                return !tx.is_closed();
+            }
+            let event = Event {
+                key: key.to_owned(),
+                kind,
+            };
+            match tx.try_send(event) {
+                Ok(()) => true,
+                Err(e) => e.is_full(),
+            }
+        });
+        self.len.store(list.len(), Ordering::Release);
+    }
+}
+
+/// Key-value map of a shard with memory usage accounting.
+pub(super) struct Store {
+    map: HashMap<String, Data>,
+    memory: usize,
+    usage: Arc<Usage>,
+    watchers: Arc<Watchers>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Store {
+    fn new(usage: Arc<Usage>, watchers: Arc<Watchers>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            map: HashMap::new(),
+            memory: 0,
+            usage,
+            watchers,
+            clock,
+        }
+    }
+
+    pub(super) fn now(&self) -> i64 {
+        self.clock.now()
+    }
+
+    /// Estimated memory usage of an entry in bytes.
+    fn size(key: &str, data: &Data) -> usize {
+        let value = match &data.0 {
+            Value::String(x) => x.len(),
+            Value::Hash(x) => x.iter().map(|(k, v)| Self::hash_size(k, v)).sum(),
+            Value::SortedSet(x) => x.iter().map(|(k, _)| Self::zset_size(k)).sum(),
+        };
+        Self::key_size(key) + value
+    }
+
+    /// Estimated memory usage of an entry without its value in bytes.
+    fn key_size(key: &str) -> usize {
+        size_of::<(String, Data)>() + key.len()
+    }
+
+    /// Estimated memory usage of a hash field in bytes.
+    fn hash_size(field: &str, value: &str) -> usize {
+        size_of::<(String, String)>() + field.len() + value.len()
+    }
+
+    /// Estimated memory usage of a sorted set member in bytes.
+    fn zset_size(member: &str) -> usize {
+        // Members are stored twice, by name and by score.
+        size_of::<(String, f64)>() + size_of::<(Score, String)>() + member.len() * 2
+    }
+
+    fn insert(&mut self, key: String, mut data: Data) {
+        data.3 = Self::size(&key, &data);
+        self.memory += data.3;
+        self.usage.memory.fetch_add(data.3, Ordering::Relaxed);
+        match self.map.insert(key, data) {
+            Some(x) => {
+                self.memory -= x.3;
+                self.usage.memory.fetch_sub(x.3, Ordering::Relaxed);
+            }
+            None => {
+                self.usage.keys.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Data> {
+        let ret = self.map.remove(key);
+        if let Some(x) = &ret {
+            self.memory -= x.3;
+            self.usage.memory.fetch_sub(x.3, Ordering::Relaxed);
+            self.usage.keys.fetch_sub(1, Ordering::Relaxed);
+        }
+        ret
+    }
+
+    /// Remove `key` if it is expired and return the old value.
+    fn remove_expired(&mut self, key: &str) -> Option<Data> {
+        let now = self.now();
+        if self.map.get(key).is_some_and(|v| !v.valid(now)) {
+            let ret = self.remove(key);
+            self.watchers.notify(key, EventKind::Expired);
+            return ret;
+        }
+        None
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut Data> {
+        self.map.get_mut(key)
+    }
+
+    /// Recalculate the memory usage of `key` after it is modified in place.
+    fn resize(&mut self, key: &str) {
+        if let Some(v) = self.map.get_mut(key) {
+            let size = Self::size(key, v);
+            self.memory = self.memory - v.3 + size;
+            self.usage.memory.fetch_add(size, Ordering::Relaxed);
+            self.usage.memory.fetch_sub(v.3, Ordering::Relaxed);
+            v.3 = size;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.usage.memory.fetch_sub(self.memory, Ordering::Relaxed);
+        self.usage.keys.fetch_sub(self.map.len(), Ordering::Relaxed);
+        self.map.clear();
+        self.memory = 0;
+    }
+}
+
+impl Deref for Store {
+    type Target = HashMap<String, Data>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+/// Independently locked shards, keys are distributed by their hash.
+///
+/// Locks of multiple shards must be acquired in order to avoid deadlocks.
+pub(super) struct Shards {
+    shards: Box<[RwLock<Store>]>,
+    hasher: RandomState,
+    usage: Arc<Usage>,
+    watchers: Arc<Watchers>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Shards {
+    pub(super) fn new(num: usize, clock: Arc<dyn Clock>) -> Self {
+        let usage = Arc::new(Usage::default());
+        let watchers = Arc::new(Watchers::default());
+        Self {
+            shards: (0..max(num, 1))
+                .map(|_| RwLock::new(Store::new(usage.clone(), watchers.clone(), clock.clone())))
+                .collect(),
+            hasher: RandomState::new(),
+            usage,
+            watchers,
+            clock,
+        }
+    }
+
+    pub(super) fn now(&self) -> i64 {
+        self.clock.now()
+    }
+
+    /// 4 shards per available CPU.
+    fn default_num() -> usize {
+        thread::available_parallelism().map_or(1, NonZeroUsize::get) * 4
+    }
+
+    /// Number of shards.
+    pub(super) fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn index(&self, key: &str) -> usize {
+        (self.hasher.hash_one(key) % self.shards.len() as u64) as usize
     }
 
-    fn parse_ttl(ttl: Option<i64>) -> Option<i64> {
-        ttl.map(|x| Self::now().saturating_add(x))
+    /// Shard holding `key`.
+    pub(super) fn shard(&self, key: &str) -> &RwLock<Store> {
+        &self.shards[self.index(key)]
     }
 
-    fn new<S>(value: S, ttl: Option<i64>) -> Self
+    /// Write lock all shards holding `keys` in order.
+    fn lock<'a, I>(&self, keys: I) -> Locks<'_>
     where
-        S: Into<String>,
+        I: IntoIterator<Item = &'a str>,
     {
-        Self(value.into(), Self::parse_ttl(ttl))
+        let index: BTreeSet<_> = keys.into_iter().map(|x| self.index(x)).collect();
+        Locks {
+            data: self,
+            wlocks: index
+                .into_iter()
+                .map(|i| (i, self.shards[i].write()))
+                .collect(),
+        }
     }
 
-    fn set_ttl(&mut self, ttl: Option<i64>) {
-        self.1 = Self::parse_ttl(ttl);
+    pub(super) fn iter(&self) -> impl Iterator<Item = &RwLock<Store>> {
+        self.shards.iter()
     }
 
-    fn get_ttl(&self) -> Option<i64> {
-        self.1.map(|x| x.saturating_sub(Self::now()))
+    /// Insert `key` without any limitation, used when loading data.
+    #[cfg(feature = "persist")]
+    pub(super) fn insert(&self, key: String, data: Data) {
+        self.shard(&key).write().insert(key, data);
     }
 
-    fn valid(&self) -> bool {
-        if let Some(x) = self.1 {
-            x > Self::now()
+    /// Number of keys in all shards.
+    fn keys(&self) -> usize {
+        self.usage.keys.load(Ordering::Relaxed)
+    }
+
+    /// Memory usage of all shards.
+    fn memory(&self) -> usize {
+        self.usage.memory.load(Ordering::Relaxed)
+    }
+}
+
+/// Write locks of multiple shards, see [`Shards::lock`].
+struct Locks<'a> {
+    data: &'a Shards,
+    wlocks: BTreeMap<usize, RwLockWriteGuard<'a, Store>>,
+}
+
+impl<'a> Locks<'a> {
+    /// Write lock of the shard holding `key`, which must be passed to [`Shards::lock`].
+    fn get(&mut self, key: &str) -> &mut RwLockWriteGuard<'a, Store> {
+        let i = self.data.index(key);
+        self.wlocks.get_mut(&i).expect("Shard is not locked")
+    }
+}
+
+/// Usage statistics of [`DefaultBackend`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of keys, including expired keys that are not removed yet.
+    pub keys: usize,
+    /// Estimated memory usage of keys and values in bytes.
+    pub memory: usize,
+    /// Number of keys evicted by the eviction policy.
+    pub evicted: u64,
+}
+
+/// Convert a binary-safe string to UTF-8.
+pub(super) fn utf8(value: Vec<u8>) -> Result<String> {
+    String::from_utf8(value).map_err(|_| anyhow!("Value is not a valid UTF-8 string"))
+}
+
+fn check_score(score: f64) -> Result<()> {
+    if score.is_nan() {
+        bail!(NAN_SCORE);
+    }
+    Ok(())
+}
+
+/// Parse `value` as an integer and add `delta` to it.
+pub(super) fn incr(value: &[u8], delta: i64) -> Result<i64> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|x| x.parse::<i64>().ok())
+        .ok_or_else(|| anyhow!("Value is not an integer"))?
+        .checked_add(delta)
+        .ok_or_else(|| anyhow!("Increment or decrement would overflow"))
+}
+
+/// Sample at most `budget` keys of `shard` starting from `cursor` and remove the expired ones,
+/// `f` is called with the valid ones.
+/// Return the number of sampled and removed keys, `cursor` is reset to 0 after the shard is fully sampled.
+///
+/// Only the read lock is held while sampling, the write lock is held briefly for removal.
+fn sweep_shard<F>(
+    shard: &RwLock<Store>,
+    cursor: &mut usize,
+    budget: usize,
+    f: &mut F,
+) -> (usize, usize)
+where
+    F: FnMut(&str, &Data),
+{
+    let rlock = shard.read();
+    let now = rlock.now();
+    let mut sampled = 0;
+    let mut expired = Vec::new();
+    for (k, v) in rlock.iter().skip(*cursor).take(budget) {
+        sampled += 1;
+        if v.valid(now) {
+            f(k, v);
         } else {
-            true
+            expired.push(k.to_owned());
+        }
+    }
+    *cursor = if sampled < budget {
+        0
+    } else {
+        *cursor + sampled
+    };
+    drop(rlock);
+
+    if expired.is_empty() {
+        return (sampled, 0);
+    }
+    let mut wlock = shard.write();
+    let mut removed = 0;
+    for i in &expired {
+        if wlock.remove_expired(i).is_some() {
+            removed += 1;
+        }
+    }
+    // Removed keys are no longer iterated.
+    *cursor = cursor.saturating_sub(removed);
+    (sampled, removed)
+}
+
+/// Sample at most `budget` keys across shards starting from `cursor` (shard, position)
+/// and remove the expired ones, `f` is called with the valid ones.
+/// Return the number of sampled and removed keys.
+///
+/// `cursor` is reset to `(0, 0)` after all shards are sampled.
+fn sweep<F>(data: &Shards, cursor: &mut (usize, usize), budget: usize, mut f: F) -> (usize, usize)
+where
+    F: FnMut(&str, &Data),
+{
+    let mut sampled = 0;
+    let mut removed = 0;
+    for _ in 0..data.len() {
+        let (s, r) = sweep_shard(
+            &data.shards[cursor.0],
+            &mut cursor.1,
+            budget - sampled,
+            &mut f,
+        );
+        sampled += s;
+        removed += r;
+        if cursor.1 == 0 {
+            cursor.0 = (cursor.0 + 1) % data.len();
+        }
+        if sampled == budget || *cursor == (0, 0) {
+            break;
+        }
+    }
+    (sampled, removed)
+}
+
+/// Number of keys sampled for each eviction.
+const EVICTION_SAMPLES: usize = 16;
+
+/// Maximum length of a formatted integer.
+const INT_SIZE: usize = 20;
+
+/// Number of events buffered for each watcher.
+const WATCH_BUFFER: usize = 1024;
+
+/// Projected size of a key after a write, see [`DefaultBackend::reserve`].
+#[derive(Clone, Copy)]
+enum Projected<'a> {
+    /// The key is replaced by a new value.
+    Replace(&'a Data),
+    /// The value of the key grows by at most the given bytes, it is created if not exists.
+    Grow(usize),
+}
+
+/// Key eviction policy when the capacity or memory limit is reached.
+///
+/// Expired keys are always evicted first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// Do not evict any valid keys, return an error instead.
+    NoEviction,
+    /// Evict keys having TTL, sorting by TTL from shortest to longest.
+    #[default]
+    VolatileTtl,
+    /// Evict least recently used keys.
+    AllKeysLru,
+    /// Evict least frequently used keys.
+    AllKeysLfu,
+    /// Evict least recently used keys having TTL.
+    VolatileLru,
+}
+
+impl EvictionPolicy {
+    /// Eviction priority of `v`, smaller is evicted first. `None` if `v` cannot be evicted.
+    fn priority(&self, v: &Data) -> Option<(i64, i64)> {
+        let time = v.2.time.load(Ordering::Relaxed);
+        match self {
+            Self::NoEviction => None,
+            Self::VolatileTtl => v.1.map(|x| (x, 0)),
+            Self::AllKeysLru => Some((time, 0)),
+            Self::AllKeysLfu => Some((
+                v.2.hits
+                    .load(Ordering::Relaxed)
+                    .try_into()
+                    .unwrap_or(i64::MAX),
+                time,
+            )),
+            Self::VolatileLru => v.1.map(|_| (time, 0)),
         }
     }
 }
 
+/// When the append-only log is synced to disk.
+///
+/// Records are written by a background thread, so writes never wait for disk I/O while holding
+/// locks. The thread flushes records to the OS as soon as it catches up, after which they survive
+/// a process crash; use [`DefaultBackend::sync`] to wait for it.
+/// Syncing only matters for OS crashes and power loss.
+#[cfg(feature = "persist")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fsync {
+    /// Never sync explicitly, leave it to the OS.
+    #[default]
+    Never,
+    /// Sync after every batch of records written by the background thread. Safest but slowest.
+    Always,
+    /// Sync on write if the last sync was longer than the interval ago.
+    ///
+    /// Writes within the last interval may be lost.
+    Interval(Duration),
+}
+
+type Subscriber = (Pattern, mpsc::UnboundedSender<Message>);
+
 #[derive(Clone)]
 pub struct DefaultBackend {
-    data: Arc<RwLock<HashMap<String, Data>>>,
+    data: Arc<Shards>,
     capacity: Option<usize>,
+    max_memory: Option<usize>,
+    eviction: EvictionPolicy,
+    evicted: Arc<AtomicU64>,
+    cursor: Arc<Mutex<(usize, usize)>>,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    #[cfg(feature = "persist")]
+    persister: Option<Arc<Persister>>,
 }
 
 impl DefaultBackend {
     pub fn new(capacity: Option<usize>) -> Self {
         Self {
-            data: Default::default(),
+            data: Arc::new(Shards::new(Shards::default_num(), Arc::new(SystemClock))),
             capacity,
+            max_memory: None,
+            eviction: EvictionPolicy::default(),
+            evicted: Default::default(),
+            cursor: Default::default(),
+            subscribers: Default::default(),
+            #[cfg(feature = "persist")]
+            persister: None,
+        }
+    }
+
+    pub fn builder() -> DefaultBackendBuilder {
+        DefaultBackendBuilder::default()
+    }
+
+    /// Current usage statistics.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            keys: self.data.keys(),
+            memory: self.data.memory(),
+            evicted: self.evicted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Write a snapshot immediately.
+    ///
+    /// A snapshot is also written when the last clone of the backend is dropped.
+    #[cfg(feature = "persist")]
+    pub fn save(&self) -> Result<()> {
+        match &self.persister {
+            Some(x) => x.snapshot(),
+            None => bail!("Persistence is not enabled"),
+        }
+    }
+
+    /// Wait until all writes are written to the append-only log and synced to disk.
+    ///
+    /// No-op if only snapshots are enabled.
+    #[cfg(feature = "persist")]
+    pub fn sync(&self) -> Result<()> {
+        match &self.persister {
+            Some(x) => x.sync(),
+            None => bail!("Persistence is not enabled"),
         }
     }
 
-    /// Evict `num` keys from memory. Return evicted number.
+    /// Evict keys until `done` returns true, `keep` is never evicted.
+    ///
+    /// Each round samples [`EVICTION_SAMPLES`] keys, removes the expired ones and evicts the
+    /// best candidate according to the eviction policy, similar to the approximated LRU of Redis.
+    /// Stop if no key can be evicted after all keys are sampled.
     ///
-    /// - Evict any expired keys (`x`).
-    /// - If `x < num`, evict at most `num-x` keys sorted by TTL.
-    fn gc(&self, wlock: &mut RwLockWriteGuard<HashMap<String, Data>>, num: usize) -> usize {
-        let mut queue = PriorityQueue::new();
-        let mut delete = Vec::new();
-        for (k, v) in wlock.iter() {
-            if !v.valid() {
-                delete.push(k.to_owned());
-            } else if let Some(x) = v.1 {
-                queue.push(k.to_owned(), Reverse(x));
-            }
-        }
-        for i in &delete {
-            wlock.remove(i);
-        }
-        let mut ret = delete.len();
-        if ret < num {
-            let remain = num - ret;
-            for _ in 0..remain {
-                if let Some(k) = queue.pop() {
-                    wlock.remove(&k.0);
-                    ret += 1;
-                } else {
-                    return ret;
+    /// Only one shard is locked at a time.
+    fn gc<F>(&self, keep: &str, done: F) -> Result<()>
+    where
+        F: Fn() -> bool,
+    {
+        let mut cursor = self.cursor.lock();
+        let mut idle = 0;
+        while !done() {
+            let mut best: Option<(String, (i64, i64))> = None;
+            let mut f = |k: &str, v: &Data| {
+                if k == keep {
+                    return;
+                }
+                if let Some(x) = self.eviction.priority(v) {
+                    if best.as_ref().is_none_or(|(_, p)| x < *p) {
+                        best = Some((k.to_owned(), x));
+                    }
+                }
+            };
+            let (mut sampled, mut removed) =
+                sweep(&self.data, &mut cursor, EVICTION_SAMPLES, &mut f);
+            // Continue from the first shard so that small key spaces are fully sampled.
+            if sampled < EVICTION_SAMPLES && *cursor == (0, 0) {
+                let (s, r) = sweep(&self.data, &mut cursor, EVICTION_SAMPLES - sampled, &mut f);
+                sampled += s;
+                removed += r;
+            }
+            if let Some((k, _)) = best {
+                if self.remove(&mut self.data.shard(&k).write(), &k)?.is_some() {
+                    self.evicted.fetch_add(1, Ordering::Relaxed);
+                }
+                idle = 0;
+            } else if removed > 0 {
+                idle = 0;
+            } else {
+                idle += sampled;
+                if sampled == 0 || idle >= self.data.keys() {
+                    break;
                 }
             }
         }
+        Ok(())
+    }
+
+    /// Make room before writing `key`, evict some keys if the capacity or memory limit is reached.
+    ///
+    /// When a limit is reached, keys are evicted until 10% of it is free.
+    /// Must be called without holding any lock.
+    /// The limits may be exceeded slightly by concurrent writes.
+    fn reserve(&self, key: &str, size: Projected) -> Result<()> {
+        if let Some(x) = self.capacity {
+            let exists = || self.data.shard(key).read().contains_key(key);
+            if self.data.keys() >= x && !exists() {
+                let free = max(x / 10, 1);
+                self.gc(key, || self.data.keys() + free <= x || exists())?;
+                if self.data.keys() >= x && !exists() {
+                    bail!("Capacity is full");
+                }
+            }
+        }
+        if let Some(x) = self.max_memory {
+            let memory = || {
+                let old = self.data.shard(key).read().get(key).map(|v| v.3);
+                let new = match (size, old) {
+                    (Projected::Replace(v), _) => Store::size(key, v),
+                    (Projected::Grow(n), Some(old)) => old + n,
+                    (Projected::Grow(n), None) => Store::key_size(key) + n,
+                };
+                (self.data.memory() + new).saturating_sub(old.unwrap_or_default())
+            };
+            if memory() > x {
+                self.gc(key, || memory() <= x - x / 10)?;
+                if memory() > x {
+                    bail!("Memory is full");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the mutable value of `key`, expired key will be removed.
+    fn get_mut<'a>(wlock: &'a mut RwLockWriteGuard<Store>, key: &str) -> Option<&'a mut Data> {
+        wlock.remove_expired(key);
+        let now = wlock.now();
+        let ret = wlock.get_mut(key);
+        if let Some(v) = &ret {
+            v.2.touch(now);
+        }
         ret
     }
+
+    /// Insert `key`, [`DefaultBackend::reserve`] must be called before.
+    fn insert(&self, wlock: &mut RwLockWriteGuard<Store>, key: &str, data: Data) -> Result<()> {
+        wlock.insert(key.to_owned(), data);
+        self.modified(wlock, key)
+    }
+
+    /// Remove `key` and return the old value.
+    fn remove(&self, wlock: &mut RwLockWriteGuard<Store>, key: &str) -> Result<Option<Data>> {
+        let ret = wlock.remove(key);
+        if ret.is_some() {
+            self.modified(wlock, key)?;
+        }
+        Ok(ret)
+    }
+
+    /// Insert `key` if it does not exist. Return `true` if inserted.
+    fn insert_nx(&self, key: &str, data: Data) -> Result<bool> {
+        // Check before reserving, so that an existing key never causes evictions.
+        let exists = |rlock: &Store| rlock.get(key).is_some_and(|v| v.valid(rlock.now()));
+        if exists(&self.data.shard(key).read()) {
+            return Ok(false);
+        }
+        self.reserve(key, Projected::Replace(&data))?;
+        let mut wlock = self.data.shard(key).write();
+        if Self::get_mut(&mut wlock, key).is_some() {
+            return Ok(false);
+        }
+        self.insert(&mut wlock, key, data)?;
+        Ok(true)
+    }
+
+    fn expire_locked(
+        &self,
+        wlock: &mut RwLockWriteGuard<Store>,
+        key: &str,
+        ttl: i64,
+    ) -> Result<bool> {
+        if ttl <= 0 {
+            return Ok(self.remove(wlock, key)?.is_some());
+        }
+        let now = wlock.now();
+        if let Some(v) = Self::get_mut(wlock, key) {
+            v.set_ttl(Some(ttl.saturating_mul(1000)), now);
+            self.modified(wlock, key)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn incr_by_locked(
+        &self,
+        wlock: &mut RwLockWriteGuard<Store>,
+        key: &str,
+        delta: i64,
+        ttl: Option<i64>,
+    ) -> Result<i64> {
+        if let Some(v) = Self::get_mut(wlock, key) {
+            let v = v.0.as_string_mut()?;
+            let ret = incr(v, delta)?;
+            *v = ret.to_string().into_bytes();
+            self.modified(wlock, key)?;
+            return Ok(ret);
+        }
+        self.insert(
+            wlock,
+            key,
+            Data::new(
+                Value::String(delta.to_string().into_bytes()),
+                ttl,
+                wlock.now(),
+            ),
+        )?;
+        Ok(delta)
+    }
+
+    fn hset_locked(
+        &self,
+        wlock: &mut RwLockWriteGuard<Store>,
+        key: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<bool> {
+        if let Some(v) = Self::get_mut(wlock, key) {
+            let ret =
+                v.0.as_hash_mut()?
+                    .insert(field.to_owned(), value.to_owned())
+                    .is_none();
+            self.modified(wlock, key)?;
+            return Ok(ret);
+        }
+        self.insert(
+            wlock,
+            key,
+            Data::new(
+                Value::Hash(HashMap::from([(field.to_owned(), value.to_owned())])),
+                None,
+                wlock.now(),
+            ),
+        )?;
+        Ok(true)
+    }
+
+    fn hdel_locked(
+        &self,
+        wlock: &mut RwLockWriteGuard<Store>,
+        key: &str,
+        field: &str,
+    ) -> Result<bool> {
+        if let Some(v) = Self::get_mut(wlock, key) {
+            let hash = v.0.as_hash_mut()?;
+            let ret = hash.remove(field).is_some();
+            if hash.is_empty() {
+                self.remove(wlock, key)?;
+            } else if ret {
+                self.modified(wlock, key)?;
+            }
+            Ok(ret)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn hincr_locked(
+        &self,
+        wlock: &mut RwLockWriteGuard<Store>,
+        key: &str,
+        field: &str,
+        delta: i64,
+    ) -> Result<i64> {
+        if let Some(v) = Self::get_mut(wlock, key) {
+            let hash = v.0.as_hash_mut()?;
+            let ret = if let Some(v) = hash.get_mut(field) {
+                let ret = incr(v.as_bytes(), delta)?;
+                *v = ret.to_string();
+                ret
+            } else {
+                hash.insert(field.to_owned(), delta.to_string());
+                delta
+            };
+            self.modified(wlock, key)?;
+            return Ok(ret);
+        }
+        self.insert(
+            wlock,
+            key,
+            Data::new(
+                Value::Hash(HashMap::from([(field.to_owned(), delta.to_string())])),
+                None,
+                wlock.now(),
+            ),
+        )?;
+        Ok(delta)
+    }
+
+    /// Must be called after `key` is modified.
+    fn modified(&self, wlock: &mut RwLockWriteGuard<Store>, key: &str) -> Result<()> {
+        wlock.resize(key);
+        let kind = if wlock.contains_key(key) {
+            EventKind::Set
+        } else {
+            EventKind::Del
+        };
+        wlock.watchers.notify(key, kind);
+        #[cfg(feature = "persist")]
+        if let Some(x) = &self.persister {
+            x.append(match wlock.get(key) {
+                Some(v) => Record::Set(key, v),
+                None => Record::Del(key),
+            })?;
+        }
+        Ok(())
+    }
 }
 
 impl Default for DefaultBackend {
@@ -103,31 +1025,220 @@ impl Default for DefaultBackend {
     }
 }
 
+#[derive(Default)]
+pub struct DefaultBackendBuilder {
+    capacity: Option<usize>,
+    max_memory: Option<usize>,
+    eviction: EvictionPolicy,
+    shards: Option<usize>,
+    sweeper: Option<(Duration, usize)>,
+    clock: Option<Arc<dyn Clock>>,
+    #[cfg(feature = "persist")]
+    snapshot: Option<PathBuf>,
+    #[cfg(feature = "persist")]
+    snapshot_interval: Option<Duration>,
+    #[cfg(feature = "persist")]
+    append_only: Option<PathBuf>,
+    #[cfg(feature = "persist")]
+    fsync: Fsync,
+}
+
+impl DefaultBackendBuilder {
+    /// Maximum number of keys.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Maximum estimated memory usage of keys and values in bytes.
+    pub fn max_memory(mut self, max_memory: usize) -> Self {
+        self.max_memory = Some(max_memory);
+        self
+    }
+
+    /// Key eviction policy when the capacity or memory limit is reached. Default is [`EvictionPolicy::VolatileTtl`].
+    pub fn eviction(mut self, eviction: EvictionPolicy) -> Self {
+        self.eviction = eviction;
+        self
+    }
+
+    /// Number of independently locked shards. Default is 4 times the available parallelism.
+    ///
+    /// Use 1 to serialise all writes.
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.shards = Some(shards);
+        self
+    }
+
+    /// Actively remove expired keys in the background, similar to Redis active expiry.
+    ///
+    /// Every `interval`, at most `budget` keys are sampled and the expired ones are removed.
+    /// Sampling is repeated while more than 25% of the sampled keys are expired.
+    ///
+    /// # Panics
+    /// `build` will panic if it is not called inside a tokio runtime.
+    pub fn sweeper(mut self, interval: Duration, budget: usize) -> Self {
+        self.sweeper = Some((interval, max(budget, 1)));
+        self
+    }
+
+    /// Time source used to expire keys. Default is [`SystemClock`].
+    ///
+    /// Use [`MockClock`](super::clock::MockClock) to control time in tests.
+    pub fn clock<C>(mut self, clock: C) -> Self
+    where
+        C: Clock + 'static,
+    {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Persist data to the snapshot file at `path`.
+    ///
+    /// The snapshot is loaded when building the backend and written when the backend is dropped.
+    #[cfg(feature = "persist")]
+    pub fn snapshot<P>(mut self, path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.snapshot = Some(path.into());
+        self
+    }
+
+    /// Write the snapshot periodically.
+    ///
+    /// # Panics
+    /// `build` will panic if it is not called inside a tokio runtime.
+    #[cfg(feature = "persist")]
+    pub fn snapshot_interval(mut self, interval: Duration) -> Self {
+        self.snapshot_interval = Some(interval);
+        self
+    }
+
+    /// Log every write to the append-only file at `path`.
+    ///
+    /// The log is replayed after loading the snapshot and truncated after each snapshot.
+    /// Each record holds the full value of the written key, so small writes to large hashes
+    /// and sorted sets grow the log quickly. Use [`snapshot_interval`](Self::snapshot_interval)
+    /// to keep it bounded.
+    #[cfg(feature = "persist")]
+    pub fn append_only<P>(mut self, path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.append_only = Some(path.into());
+        self
+    }
+
+    /// When the append-only log is synced to disk. Default is [`Fsync::Never`].
+    #[cfg(feature = "persist")]
+    pub fn fsync(mut self, fsync: Fsync) -> Self {
+        self.fsync = fsync;
+        self
+    }
+
+    pub fn build(self) -> Result<DefaultBackend> {
+        let mut ret = DefaultBackend::new(self.capacity);
+        ret.max_memory = self.max_memory;
+        ret.eviction = self.eviction;
+        let clock = self.clock.unwrap_or_else(|| Arc::new(SystemClock));
+        ret.data = Arc::new(Shards::new(
+            self.shards.unwrap_or_else(Shards::default_num),
+            clock.clone(),
+        ));
+
+        #[cfg(feature = "persist")]
+        if let Some(path) = self.snapshot {
+            let persister = Arc::new(Persister::load(
+                path,
+                self.append_only,
+                self.fsync,
+                ret.data.len(),
+                clock,
+            )?);
+            ret.data = persister.data();
+            if let Some(x) = self.snapshot_interval {
+                let weak = Arc::downgrade(&persister);
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(x);
+                    interval.tick().await;
+                    loop {
+                        interval.tick().await;
+                        let Some(persister) = weak.upgrade() else {
+                            break;
+                        };
+                        let _ = tokio::task::spawn_blocking(move || persister.snapshot()).await;
+                    }
+                });
+            }
+            ret.persister = Some(persister);
+        } else if self.append_only.is_some() {
+            bail!("Append-only log requires a snapshot file");
+        }
+
+        if let Some((interval, budget)) = self.sweeper {
+            let weak = Arc::downgrade(&ret.data);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(interval);
+                let mut cursor = (0, 0);
+                loop {
+                    interval.tick().await;
+                    let Some(data) = weak.upgrade() else {
+                        break;
+                    };
+                    loop {
+                        let (sampled, removed) = sweep(&data, &mut cursor, budget, |_, _| {});
+                        if cursor == (0, 0) || removed * 4 <= sampled {
+                            break;
+                        }
+                        tokio::task::yield_now().await;
+                    }
+                }
+            });
+        }
+
+        Ok(ret)
+    }
+}
+
 #[async_trait]
 impl MemoryDB for DefaultBackend {
     async fn set(&self, key: &str, value: &str) -> Result<()> {
-        let mut wlock = self.data.write();
-        // full
-        if let Some(x) = self.capacity {
-            if x == wlock.len()
-                && self.gc(&mut wlock, max(x / 10, 1)) == 0
-                && wlock.get(key).is_none()
-            {
-                bail!("Capacity is full");
-            }
-        }
-        wlock.insert(key.to_owned(), Data::new(value, None));
-        Ok(())
+        self.set_bytes(key, value.as_bytes()).await
     }
 
     async fn get(&self, key: &str) -> Result<Option<String>> {
-        let rlock = self.data.read();
+        self.get_bytes(key).await?.map(utf8).transpose()
+    }
+
+    async fn get_del(&self, key: &str) -> Result<Option<String>> {
+        self.get_del_bytes(key).await?.map(utf8).transpose()
+    }
+
+    async fn get_ex(&self, key: &str, ttl: &Duration) -> Result<Option<String>> {
+        self.get_ex_bytes(key, ttl).await?.map(utf8).transpose()
+    }
+
+    async fn set_ex(&self, key: &str, value: &str, ttl: &Duration) -> Result<()> {
+        self.set_ex_bytes(key, value.as_bytes(), ttl).await
+    }
+
+    async fn set_bytes(&self, key: &str, value: &[u8]) -> Result<()> {
+        let data = Data::new(Value::String(value.to_owned()), None, self.data.now());
+        self.reserve(key, Projected::Replace(&data))?;
+        self.insert(&mut self.data.shard(key).write(), key, data)
+    }
+
+    async fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let rlock = self.data.shard(key).read();
+        let now = rlock.now();
         if let Some(v) = rlock.get(key) {
-            if v.valid() {
-                Ok(Some(v.0.to_owned()))
+            if v.valid(now) {
+                v.2.touch(now);
+                Ok(Some(v.0.as_string()?.to_owned()))
             } else {
                 drop(rlock);
-                self.data.write().remove(key);
+                self.data.shard(key).write().remove_expired(key);
                 Ok(None)
             }
         } else {
@@ -135,111 +1246,556 @@ impl MemoryDB for DefaultBackend {
         }
     }
 
-    async fn get_del(&self, key: &str) -> Result<Option<String>> {
-        let v = self.data.write().remove(key);
-        if let Some(v) = v {
-            if v.valid() {
-                return Ok(Some(v.0));
+    async fn get_del_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let mut wlock = self.data.shard(key).write();
+        if let Some(v) = Self::get_mut(&mut wlock, key) {
+            v.0.as_string()?;
+            if let Some(Data(Value::String(x), ..)) = self.remove(&mut wlock, key)? {
+                return Ok(Some(x));
             }
         }
         Ok(None)
     }
 
-    async fn get_ex(&self, key: &str, ttl: &Duration) -> Result<Option<String>> {
-        let mut wlock = self.data.write();
-        if let Some(v) = wlock.get_mut(key) {
-            if v.valid() {
-                v.set_ttl(Some(ttl.as_secs().try_into()?));
-                Ok(Some(v.0.to_owned()))
-            } else {
-                wlock.remove(key);
-                Ok(None)
-            }
+    async fn get_ex_bytes(&self, key: &str, ttl: &Duration) -> Result<Option<Vec<u8>>> {
+        let mut wlock = self.data.shard(key).write();
+        let now = wlock.now();
+        if let Some(v) = Self::get_mut(&mut wlock, key) {
+            let ret = v.0.as_string()?.to_owned();
+            v.set_ttl(Some(ttl.as_millis().try_into()?), now);
+            self.modified(&mut wlock, key)?;
+            Ok(Some(ret))
         } else {
             Ok(None)
         }
     }
 
-    async fn set_ex(&self, key: &str, value: &str, ttl: &Duration) -> Result<()> {
-        let mut wlock = self.data.write();
-        // full
-        if let Some(x) = self.capacity {
-            if x == wlock.len()
-                && self.gc(&mut wlock, max(x / 10, 1)) == 0
-                && wlock.get(key).is_none()
-            {
-                bail!("Capacity is full");
-            }
-        }
-        wlock.insert(
-            key.to_owned(),
-            Data::new(value, Some(ttl.as_secs().try_into()?)),
+    async fn set_ex_bytes(&self, key: &str, value: &[u8], ttl: &Duration) -> Result<()> {
+        let data = Data::new(
+            Value::String(value.to_owned()),
+            Some(ttl.as_millis().try_into()?),
+            self.data.now(),
         );
-        Ok(())
+        self.reserve(key, Projected::Replace(&data))?;
+        self.insert(&mut self.data.shard(key).write(), key, data)
+    }
+
+    async fn set_nx(&self, key: &str, value: &str) -> Result<bool> {
+        let data = Data::new(
+            Value::String(value.as_bytes().to_owned()),
+            None,
+            self.data.now(),
+        );
+        self.insert_nx(key, data)
+    }
+
+    async fn set_ex_nx(&self, key: &str, value: &str, ttl: &Duration) -> Result<bool> {
+        let data = Data::new(
+            Value::String(value.as_bytes().to_owned()),
+            Some(ttl.as_millis().try_into()?),
+            self.data.now(),
+        );
+        self.insert_nx(key, data)
     }
 
     async fn del(&self, key: &str) -> Result<bool> {
-        Ok(self.data.write().remove(key).is_some())
+        Ok(self
+            .remove(&mut self.data.shard(key).write(), key)?
+            .is_some())
+    }
+
+    async fn del_if_eq(&self, key: &str, value: &str) -> Result<bool> {
+        let mut wlock = self.data.shard(key).write();
+        if let Some(v) = Self::get_mut(&mut wlock, key) {
+            if v.0.as_string()? == value.as_bytes() {
+                self.remove(&mut wlock, key)?;
+                return Ok(true);
+            }
+        }
+        Ok(false)
     }
 
     async fn expire(&self, key: &str, ttl: i64) -> Result<bool> {
-        if ttl <= 0 {
-            self.del(key).await
-        } else {
-            let mut wlock = self.data.write();
-            if let Some(v) = wlock.get_mut(key) {
-                if v.valid() {
-                    v.set_ttl(Some(ttl));
-                    Ok(true)
-                } else {
-                    wlock.remove(key);
-                    Ok(false)
-                }
-            } else {
-                Ok(false)
+        self.expire_locked(&mut self.data.shard(key).write(), key, ttl)
+    }
+
+    async fn expire_if_eq(&self, key: &str, value: &str, ttl: &Duration) -> Result<bool> {
+        let mut wlock = self.data.shard(key).write();
+        let now = wlock.now();
+        if let Some(v) = Self::get_mut(&mut wlock, key) {
+            if v.0.as_string()? == value.as_bytes() {
+                v.set_ttl(Some(ttl.as_millis().try_into()?), now);
+                self.modified(&mut wlock, key)?;
+                return Ok(true);
             }
         }
+        Ok(false)
     }
 
     async fn flush(&self) -> Result<()> {
-        self.data.write().clear();
+        let mut wlocks: Vec<_> = self.data.iter().map(|x| x.write()).collect();
+        for i in &mut wlocks {
+            i.clear();
+        }
+        #[cfg(feature = "persist")]
+        if let Some(x) = &self.persister {
+            x.append(Record::Flush)?;
+        }
         Ok(())
     }
 
     async fn keys(&self, key: &str) -> Result<Vec<String>> {
         let mut ret = Vec::new();
         let p = Pattern::new(key)?;
-        for (k, v) in self.data.read().iter() {
-            if v.valid() && p.matches(k) {
-                ret.push(k.to_owned());
+        let now = self.data.now();
+        for shard in self.data.iter() {
+            for (k, v) in shard.read().iter() {
+                if v.valid(now) && p.matches(k) {
+                    ret.push(k.to_owned());
+                }
             }
         }
         Ok(ret)
     }
 
+    async fn scan(&self, pattern: &str, _count: usize) -> Result<KeyStream> {
+        let p = Pattern::new(pattern)?;
+        let data = self.data.clone();
+        // Keys are collected one shard at a time, so each lock is held briefly.
+        Ok(stream::iter(0..data.len())
+            .flat_map(move |i| {
+                let now = data.now();
+                let keys: Vec<_> = data.shards[i]
+                    .read()
+                    .iter()
+                    .filter(|(k, v)| v.valid(now) && p.matches(k))
+                    .map(|(k, _)| Ok(k.to_owned()))
+                    .collect();
+                stream::iter(keys)
+            })
+            .boxed())
+    }
+
     async fn dels(&self, keys: &[String]) -> Result<u64> {
-        let mut wlock = self.data.write();
+        let mut locks = self.data.lock(keys.iter().map(String::as_str));
         let mut sum = 0;
         for i in keys {
-            if wlock.remove(i).is_some() {
+            if self.remove(locks.get(i), i)?.is_some() {
                 sum += 1;
             }
         }
         Ok(sum)
     }
 
+    async fn mget(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        // Read every key under the same locks, so the result is a consistent view.
+        let mut locks = self.data.lock(keys.iter().map(String::as_str));
+        keys.iter()
+            .map(|k| match Self::get_mut(locks.get(k), k) {
+                Some(v) => Ok(Some(utf8(v.0.as_string()?.to_owned())?)),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    async fn exec(&self, tx: &Transaction) -> Result<Vec<Reply>> {
+        // Make room before locking, the same as single key writes.
+        // An op that fails here is skipped, the rest still run without rollback.
+        let prepare = |i: &Op| -> Result<Option<Data>> {
+            let x = match i {
+                Op::Set(_, v, ttl) => Some(Data::new(
+                    Value::String(v.to_owned()),
+                    ttl.map(|x| x.as_millis().try_into()).transpose()?,
+                    self.data.now(),
+                )),
+                _ => None,
+            };
+            let size = match (i, &x) {
+                (Op::Set(..), Some(x)) => Some(Projected::Replace(x)),
+                (Op::IncrBy(..), _) => Some(Projected::Grow(INT_SIZE)),
+                (Op::HSet(_, f, v), _) => Some(Projected::Grow(Store::hash_size(f, v))),
+                (Op::HIncr(_, f, _), _) => {
+                    Some(Projected::Grow(Store::hash_size(f, "") + INT_SIZE))
+                }
+                _ => None,
+            };
+            if let Some(size) = size {
+                self.reserve(i.key(), size)?;
+            }
+            Ok(x)
+        };
+        let data: Vec<_> = tx.ops().iter().map(prepare).collect();
+
+        let mut locks = self.data.lock(tx.ops().iter().map(Op::key));
+        let mut ret = Vec::with_capacity(tx.ops().len());
+        let mut err = None;
+        for (i, x) in tx.ops().iter().zip(data) {
+            let wlock = locks.get(i.key());
+            let reply = match (i, x) {
+                (_, Err(e)) => Err(e),
+                (Op::Set(k, ..), Ok(Some(x))) => self.insert(wlock, k, x).map(|_| Reply::Ok),
+                (Op::Set(..), Ok(None)) => unreachable!(),
+                (Op::Del(k), _) => self.remove(wlock, k).map(|x| Reply::Bool(x.is_some())),
+                (Op::Expire(k, ttl), _) => self.expire_locked(wlock, k, *ttl).map(Reply::Bool),
+                (Op::IncrBy(k, delta), _) => {
+                    self.incr_by_locked(wlock, k, *delta, None).map(Reply::Int)
+                }
+                (Op::HSet(k, f, v), _) => self.hset_locked(wlock, k, f, v).map(Reply::Bool),
+                (Op::HDel(k, f), _) => self.hdel_locked(wlock, k, f).map(Reply::Bool),
+                (Op::HIncr(k, f, delta), _) => {
+                    self.hincr_locked(wlock, k, f, *delta).map(Reply::Int)
+                }
+            };
+            match reply {
+                Ok(x) => ret.push(x),
+                Err(e) => {
+                    err.get_or_insert(e);
+                }
+            }
+        }
+        match err {
+            Some(e) => Err(e),
+            None => Ok(ret),
+        }
+    }
+
     async fn ttl(&self, key: &str) -> Result<Option<i64>> {
-        let rlock = self.data.read();
+        // Round to the nearest second, the same as Redis.
+        Ok(self.pttl(key).await?.map(|x| (x + 500) / 1000))
+    }
+
+    async fn pttl(&self, key: &str) -> Result<Option<i64>> {
+        let rlock = self.data.shard(key).read();
+        let now = rlock.now();
+        if let Some(v) = rlock.get(key) {
+            if v.valid(now) {
+                Ok(v.get_ttl(now))
+            } else {
+                drop(rlock);
+                self.data.shard(key).write().remove_expired(key);
+                Ok(None)
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn incr_by(&self, key: &str, delta: i64, ttl: Option<&Duration>) -> Result<i64> {
+        let ttl = match ttl {
+            Some(x) if x.as_millis() > 0 => Some(x.as_millis().try_into()?),
+            _ => None,
+        };
+        self.reserve(key, Projected::Grow(INT_SIZE))?;
+        self.incr_by_locked(&mut self.data.shard(key).write(), key, delta, ttl)
+    }
+
+    async fn decr_by(&self, key: &str, delta: i64, ttl: Option<&Duration>) -> Result<i64> {
+        let delta = delta
+            .checked_neg()
+            .ok_or_else(|| anyhow!("Increment or decrement would overflow"))?;
+        self.incr_by(key, delta, ttl).await
+    }
+
+    async fn hset(&self, key: &str, field: &str, value: &str) -> Result<bool> {
+        self.reserve(key, Projected::Grow(Store::hash_size(field, value)))?;
+        self.hset_locked(&mut self.data.shard(key).write(), key, field, value)
+    }
+
+    async fn hget(&self, key: &str, field: &str) -> Result<Option<String>> {
+        let rlock = self.data.shard(key).read();
+        let now = rlock.now();
         if let Some(v) = rlock.get(key) {
-            if v.valid() {
-                Ok(v.get_ttl())
+            if v.valid(now) {
+                v.2.touch(now);
+                Ok(v.0.as_hash()?.get(field).cloned())
             } else {
                 drop(rlock);
-                self.data.write().remove(key);
+                self.data.shard(key).write().remove_expired(key);
                 Ok(None)
             }
         } else {
             Ok(None)
         }
     }
+
+    async fn hdel(&self, key: &str, field: &str) -> Result<bool> {
+        self.hdel_locked(&mut self.data.shard(key).write(), key, field)
+    }
+
+    async fn hgetall(&self, key: &str) -> Result<HashMap<String, String>> {
+        let rlock = self.data.shard(key).read();
+        let now = rlock.now();
+        if let Some(v) = rlock.get(key) {
+            if v.valid(now) {
+                v.2.touch(now);
+                Ok(v.0.as_hash()?.clone())
+            } else {
+                drop(rlock);
+                self.data.shard(key).write().remove_expired(key);
+                Ok(HashMap::new())
+            }
+        } else {
+            Ok(HashMap::new())
+        }
+    }
+
+    async fn hincr(&self, key: &str, field: &str, delta: i64) -> Result<i64> {
+        self.reserve(key, Projected::Grow(Store::hash_size(field, "") + INT_SIZE))?;
+        self.hincr_locked(&mut self.data.shard(key).write(), key, field, delta)
+    }
+
+    async fn zadd(&self, key: &str, member: &str, score: f64) -> Result<bool> {
+        check_score(score)?;
+        self.reserve(key, Projected::Grow(Store::zset_size(member)))?;
+        let mut wlock = self.data.shard(key).write();
+        if let Some(v) = Self::get_mut(&mut wlock, key) {
+            let ret = v.0.as_zset_mut()?.insert(member, score);
+            self.modified(&mut wlock, key)?;
+            return Ok(ret);
+        }
+        let mut zset = ZSet::default();
+        zset.insert(member, score);
+        self.insert(
+            &mut wlock,
+            key,
+            Data::new(Value::SortedSet(zset), None, self.data.now()),
+        )?;
+        Ok(true)
+    }
+
+    async fn zrem(&self, key: &str, member: &str) -> Result<bool> {
+        let mut wlock = self.data.shard(key).write();
+        if let Some(v) = Self::get_mut(&mut wlock, key) {
+            let zset = v.0.as_zset_mut()?;
+            let ret = zset.remove(member);
+            if zset.is_empty() {
+                self.remove(&mut wlock, key)?;
+            } else if ret {
+                self.modified(&mut wlock, key)?;
+            }
+            Ok(ret)
+        } else {
+            Ok(false)
+        }
+    }
+
+    async fn zrange_by_score(&self, key: &str, min: f64, max: f64) -> Result<Vec<(String, f64)>> {
+        self.zrange_by_score_limit(key, min, max, 0, usize::MAX)
+            .await
+    }
+
+    async fn zrange_by_score_limit(
+        &self,
+        key: &str,
+        min: f64,
+        max: f64,
+        offset: usize,
+        count: usize,
+    ) -> Result<Vec<(String, f64)>> {
+        check_score(min)?;
+        check_score(max)?;
+        let rlock = self.data.shard(key).read();
+        let now = rlock.now();
+        if let Some(v) = rlock.get(key) {
+            if v.valid(now) {
+                v.2.touch(now);
+                Ok(v.0
+                    .as_zset()?
+                    .range(min, max)
+                    .skip(offset)
+                    .take(count)
+                    .map(|(x, member)| (member.to_owned(), x.0))
+                    .collect())
+            } else {
+                drop(rlock);
+                self.data.shard(key).write().remove_expired(key);
+                Ok(Vec::new())
+            }
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    async fn zrem_range_by_score(&self, key: &str, min: f64, max: f64) -> Result<u64> {
+        check_score(min)?;
+        check_score(max)?;
+        let mut wlock = self.data.shard(key).write();
+        if let Some(v) = Self::get_mut(&mut wlock, key) {
+            let zset = v.0.as_zset_mut()?;
+            let ret = zset.remove_range(min, max);
+            if zset.is_empty() {
+                self.remove(&mut wlock, key)?;
+            } else if ret > 0 {
+                self.modified(&mut wlock, key)?;
+            }
+            Ok(ret)
+        } else {
+            Ok(0)
+        }
+    }
+
+    async fn zcard(&self, key: &str) -> Result<u64> {
+        let rlock = self.data.shard(key).read();
+        let now = rlock.now();
+        if let Some(v) = rlock.get(key) {
+            if v.valid(now) {
+                v.2.touch(now);
+                Ok(v.0.as_zset()?.len() as u64)
+            } else {
+                drop(rlock);
+                self.data.shard(key).write().remove_expired(key);
+                Ok(0)
+            }
+        } else {
+            Ok(0)
+        }
+    }
+
+    async fn publish(&self, channel: &str, message: &str) -> Result<u64> {
+        let mut sum = 0;
+        self.subscribers.lock().retain(|(p, tx)| {
+            if !p.matches(channel) {
+                return !tx.is_closed();
+            }
+            let ret = tx.unbounded_send(Message {
+                channel: channel.to_owned(),
+                payload: message.to_owned(),
+            });
+            if ret.is_ok() {
+                sum += 1;
+            }
+            ret.is_ok()
+        });
+        Ok(sum)
+    }
+
+    async fn subscribe(&self, pattern: &str) -> Result<MessageStream> {
+        let p = Pattern::new(pattern)?;
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().push((p, tx));
+        Ok(rx.boxed())
+    }
+
+    async fn watch(&self, pattern: &str) -> Result<EventStream> {
+        let p = Pattern::new(pattern)?;
+        let (tx, rx) = mpsc::channel(WATCH_BUFFER);
+        self.data.watchers.add((p, tx));
+        Ok(rx.boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::memorydb::clock::MockClock;
+
+    #[tokio::test]
+    async fn test_clock() {
+        let clock = MockClock::at(0);
+        let r = DefaultBackend::builder()
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        r.set_ex("key1", "value", &Duration::from_millis(1500))
+            .await
+            .unwrap();
+        r.incr_by("key2", 1, Some(&Duration::from_secs(2)))
+            .await
+            .unwrap();
+        clock.advance(Duration::from_millis(1000));
+        assert_eq!(r.pttl("key1").await.unwrap(), Some(500));
+        assert_eq!(r.ttl("key2").await.unwrap(), Some(1));
+        assert!(r.expire("key2", 1).await.unwrap());
+        clock.set(1499);
+        assert_eq!(r.get("key1").await.unwrap().unwrap(), "value");
+        clock.set(1500);
+        assert_eq!(r.get("key1").await.unwrap(), None);
+        assert_eq!(r.pttl("key2").await.unwrap(), Some(500));
+        clock.advance(Duration::from_millis(300));
+        assert_eq!(r.ttl("key2").await.unwrap(), Some(0));
+        clock.advance(Duration::from_millis(200));
+        assert!(r.keys("*").await.unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sweeper() {
+        let clock = MockClock::new();
+        let r = DefaultBackend::builder()
+            .sweeper(Duration::from_millis(50), 10)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        for i in 0..100 {
+            r.set_ex(&i.to_string(), "value", &Duration::from_millis(100))
+                .await
+                .unwrap();
+        }
+        r.set("key", "value").await.unwrap();
+        assert_eq!(r.stats().keys, 101);
+        let events = r.watch("*").await.unwrap();
+        clock.advance(Duration::from_millis(100));
+        sleep(Duration::from_millis(500)).await;
+        assert_eq!(r.stats().keys, 1);
+        assert_eq!(r.get("key").await.unwrap().unwrap(), "value");
+        let events: Vec<_> = events.take(100).collect().await;
+        assert!(events.iter().all(|x| x.kind == EventKind::Expired));
+
+        let weak = Arc::downgrade(&r.data);
+        drop(r);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_evict_sampled() {
+        let clock = MockClock::at(0);
+        let r = DefaultBackend::builder()
+            .capacity(100)
+            .eviction(EvictionPolicy::AllKeysLru)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        for i in 0..1000 {
+            clock.advance(Duration::from_millis(1));
+            r.set(&i.to_string(), "value").await.unwrap();
+        }
+        assert!(r.stats().keys <= 100);
+        assert!(r.stats().evicted >= 900);
+        assert!(r.get("0").await.unwrap().is_none());
+        assert!(r.get("999").await.unwrap().is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_shards() {
+        let r = DefaultBackend::builder().shards(4).build().unwrap();
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let r = r.clone();
+                tokio::spawn(async move {
+                    for i in 0..1000 {
+                        r.incr_by(&(i % 10).to_string(), 1, None).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for i in handles {
+            i.await.unwrap();
+        }
+        for i in 0..10 {
+            assert_eq!(r.get(&i.to_string()).await.unwrap().unwrap(), "800");
+        }
+        assert_eq!(r.keys("*").await.unwrap().len(), 10);
+        assert_eq!(r.stats().keys, 10);
+
+        r.flush().await.unwrap();
+        assert_eq!(
+            r.stats(),
+            Stats {
+                keys: 0,
+                memory: 0,
+                evicted: 0
+            }
+        );
+    }
 }
@@ -0,0 +1,198 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::interface::MemoryDB;
+use crate::Result;
+
+/// Serialization format of typed values.
+pub trait Codec {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T>;
+}
+
+/// JSON codec, values are readable with [`MemoryDB::get`].
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(Into::into)
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+        serde_json::from_slice(data).map_err(Into::into)
+    }
+}
+
+/// Bincode codec with the standard configuration.
+#[cfg(feature = "memorydb-bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "memorydb-bincode")]
+impl Codec for Bincode {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        bincode::serde::encode_to_vec(value, bincode::config::standard()).map_err(Into::into)
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+        Ok(bincode::serde::decode_from_slice(data, bincode::config::standard())?.0)
+    }
+}
+
+/// MessagePack codec, structs are encoded as maps.
+#[cfg(feature = "memorydb-msgpack")]
+pub struct MsgPack;
+
+#[cfg(feature = "memorydb-msgpack")]
+impl Codec for MsgPack {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        rmp_serde::to_vec_named(value).map_err(Into::into)
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+        rmp_serde::from_slice(data).map_err(Into::into)
+    }
+}
+
+/// Typed values over [`MemoryDB`], implemented for every backend including `Arc<dyn MemoryDB>`.
+///
+/// ```no_run
+/// # async fn f(db: std::sync::Arc<dyn actix_cloud::memorydb::MemoryDB>) -> actix_cloud::Result<()> {
+/// use actix_cloud::memorydb::ext::{Json, MemoryDBExt};
+///
+/// db.set_json("key", &vec![1, 2, 3]).await?;
+/// let v: Option<Vec<i32>> = db.get_as::<Json, _>("key").await?;
+/// # Ok(())
+/// # }
+/// ```
+#[async_trait]
+pub trait MemoryDBExt: MemoryDB {
+    async fn get_as<C, T>(&self, key: &str) -> Result<Option<T>>
+    where
+        C: Codec,
+        T: DeserializeOwned,
+    {
+        self.get_bytes(key)
+            .await?
+            .map(|x| C::decode(&x))
+            .transpose()
+    }
+
+    async fn get_ex_as<C, T>(&self, key: &str, ttl: &Duration) -> Result<Option<T>>
+    where
+        C: Codec,
+        T: DeserializeOwned,
+    {
+        self.get_ex_bytes(key, ttl)
+            .await?
+            .map(|x| C::decode(&x))
+            .transpose()
+    }
+
+    async fn set_as<C, T>(&self, key: &str, value: &T) -> Result<()>
+    where
+        C: Codec,
+        T: Serialize + Sync + ?Sized,
+    {
+        self.set_bytes(key, &C::encode(value)?).await
+    }
+
+    async fn set_ex_as<C, T>(&self, key: &str, value: &T, ttl: &Duration) -> Result<()>
+    where
+        C: Codec,
+        T: Serialize + Sync + ?Sized,
+    {
+        self.set_ex_bytes(key, &C::encode(value)?, ttl).await
+    }
+
+    async fn get_json<T>(&self, key: &str) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        self.get_as::<Json, T>(key).await
+    }
+
+    async fn get_ex_json<T>(&self, key: &str, ttl: &Duration) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        self.get_ex_as::<Json, T>(key, ttl).await
+    }
+
+    async fn set_json<T>(&self, key: &str, value: &T) -> Result<()>
+    where
+        T: Serialize + Sync + ?Sized,
+    {
+        self.set_as::<Json, T>(key, value).await
+    }
+
+    async fn set_ex_json<T>(&self, key: &str, value: &T, ttl: &Duration) -> Result<()>
+    where
+        T: Serialize + Sync + ?Sized,
+    {
+        self.set_ex_as::<Json, T>(key, value, ttl).await
+    }
+}
+
+impl<M: MemoryDB + ?Sized> MemoryDBExt for M {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::memorydb::default::DefaultBackend;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        id: u64,
+        name: String,
+    }
+
+    async fn test_codec<C: Codec>(db: &dyn MemoryDB) {
+        let key = "_actix_cloud_ext1";
+        let item = Item {
+            id: 1,
+            name: "name".to_owned(),
+        };
+
+        db.set_as::<C, _>(key, &item).await.unwrap();
+        assert_eq!(db.get_as::<C, Item>(key).await.unwrap().unwrap(), item);
+        db.set_ex_as::<C, _>(key, &item, &Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(
+            db.get_ex_as::<C, Item>(key, &Duration::from_secs(20))
+                .await
+                .unwrap()
+                .unwrap(),
+            item
+        );
+        assert_eq!(db.ttl(key).await.unwrap(), Some(20));
+        assert!(db.del(key).await.unwrap());
+        assert_eq!(db.get_as::<C, Item>(key).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_ext() {
+        let db: Arc<dyn MemoryDB> = Arc::new(DefaultBackend::new(None));
+        let key = "_actix_cloud_ext2";
+
+        test_codec::<Json>(db.as_ref()).await;
+        #[cfg(feature = "memorydb-bincode")]
+        test_codec::<Bincode>(db.as_ref()).await;
+        #[cfg(feature = "memorydb-msgpack")]
+        test_codec::<MsgPack>(db.as_ref()).await;
+
+        db.set_json(key, &[1, 2, 3]).await.unwrap();
+        assert_eq!(db.get(key).await.unwrap().unwrap(), "[1,2,3]");
+        assert_eq!(
+            db.get_json::<Vec<i32>>(key).await.unwrap().unwrap(),
+            [1, 2, 3]
+        );
+        assert!(db.get_json::<Item>(key).await.is_err());
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tracing::{debug, debug_span, warn, Instrument};
+
+use super::interface::{EventStream, KeyStream, MemoryDB, MessageStream, Reply, Transaction};
+use crate::Result;
+
+type KeyPatternFn = Arc<dyn Fn(&str) -> String + Send + Sync>;
+type EvictionsFn = Arc<dyn Fn() -> u64 + Send + Sync>;
+
+/// Counters of [`Instrumented`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Lookups returning a value.
+    pub hits: u64,
+    /// Lookups returning nothing.
+    pub misses: u64,
+    /// Failed operations.
+    pub errors: u64,
+    /// Keys evicted by the inner backend, see [`Instrumented::evictions`].
+    pub evictions: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// Keep the key up to the last `:` and replace the rest with `*`, e.g. `user:1` becomes `user:*`.
+fn default_key_pattern(key: &str) -> String {
+    match key.rfind(':') {
+        Some(x) => format!("{}*", &key[..=x]),
+        None => String::from("*"),
+    }
+}
+
+/// [`MemoryDB`] wrapper emitting a `memorydb` tracing span per operation and counting metrics.
+///
+/// Each span has the operation name `op` and the key pattern `key`.
+/// A debug event with the latency in microseconds is emitted when the operation succeeds,
+/// otherwise a warning with the error.
+///
+/// ```no_run
+/// # fn f() {
+/// use std::sync::Arc;
+/// use actix_cloud::memorydb::{default::DefaultBackend, instrumented::Instrumented};
+///
+/// let db = Arc::new(DefaultBackend::new(None));
+/// let stats = db.clone();
+/// let db = Instrumented::new(db).evictions(move || stats.stats().evicted);
+/// let metrics = db.metrics();
+/// # }
+/// ```
+pub struct Instrumented<M: ?Sized> {
+    db: Arc<M>,
+    counters: Arc<Counters>,
+    key_pattern: KeyPatternFn,
+    evictions: Option<EvictionsFn>,
+}
+
+impl<M: ?Sized> Clone for Instrumented<M> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            counters: self.counters.clone(),
+            key_pattern: self.key_pattern.clone(),
+            evictions: self.evictions.clone(),
+        }
+    }
+}
+
+impl<M: MemoryDB + ?Sized> Instrumented<M> {
+    pub fn new(db: Arc<M>) -> Self {
+        Self {
+            db,
+            counters: Default::default(),
+            key_pattern: Arc::new(default_key_pattern),
+            evictions: None,
+        }
+    }
+
+    /// Map keys to low-cardinality patterns in spans, so that sensitive keys such as
+    /// session IDs are not logged. By default, the part after the last `:` is replaced with `*`.
+    pub fn key_pattern<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.key_pattern = Arc::new(f);
+        self
+    }
+
+    /// Source of the eviction counter, the wrapper itself cannot observe evictions.
+    pub fn evictions<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> u64 + Send + Sync + 'static,
+    {
+        self.evictions = Some(Arc::new(f));
+        self
+    }
+
+    /// Inner database.
+    pub fn inner(&self) -> &Arc<M> {
+        &self.db
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            errors: self.counters.errors.load(Ordering::Relaxed),
+            evictions: self.evictions.as_ref().map_or(0, |f| f()),
+        }
+    }
+
+    async fn call<T, F>(&self, op: &'static str, key: &str, f: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        let span = debug_span!("memorydb", op, key = (self.key_pattern)(key));
+        async {
+            let start = Instant::now();
+            let ret = f.await;
+            let latency = start.elapsed().as_micros();
+            match &ret {
+                Ok(_) => debug!(latency, "MemoryDB operation finished"),
+                Err(e) => {
+                    self.counters.errors.fetch_add(1, Ordering::Relaxed);
+                    warn!(latency, error = %e, "MemoryDB operation failed");
+                }
+            }
+            ret
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Same as [`Instrumented::call`], count hits and misses.
+    async fn lookup<T, F>(&self, op: &'static str, key: &str, f: F) -> Result<Option<T>>
+    where
+        F: Future<Output = Result<Option<T>>>,
+    {
+        let ret = self.call(op, key, f).await;
+        match &ret {
+            Ok(Some(_)) => self.counters.hits.fetch_add(1, Ordering::Relaxed),
+            Ok(None) => self.counters.misses.fetch_add(1, Ordering::Relaxed),
+            Err(_) => 0,
+        };
+        ret
+    }
+}
+
+#[async_trait]
+impl<M: MemoryDB + ?Sized> MemoryDB for Instrumented<M> {
+    async fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.call("set", key, self.db.set(key, value)).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        self.lookup("get", key, self.db.get(key)).await
+    }
+
+    async fn get_del(&self, key: &str) -> Result<Option<String>> {
+        self.lookup("get_del", key, self.db.get_del(key)).await
+    }
+
+    async fn get_ex(&self, key: &str, ttl: &Duration) -> Result<Option<String>> {
+        self.lookup("get_ex", key, self.db.get_ex(key, ttl)).await
+    }
+
+    async fn set_ex(&self, key: &str, value: &str, ttl: &Duration) -> Result<()> {
+        self.call("set_ex", key, self.db.set_ex(key, value, ttl))
+            .await
+    }
+
+    async fn set_bytes(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.call("set_bytes", key, self.db.set_bytes(key, value))
+            .await
+    }
+
+    async fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.lookup("get_bytes", key, self.db.get_bytes(key)).await
+    }
+
+    async fn get_del_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.lookup("get_del_bytes", key, self.db.get_del_bytes(key))
+            .await
+    }
+
+    async fn get_ex_bytes(&self, key: &str, ttl: &Duration) -> Result<Option<Vec<u8>>> {
+        self.lookup("get_ex_bytes", key, self.db.get_ex_bytes(key, ttl))
+            .await
+    }
+
+    async fn set_ex_bytes(&self, key: &str, value: &[u8], ttl: &Duration) -> Result<()> {
+        self.call("set_ex_bytes", key, self.db.set_ex_bytes(key, value, ttl))
+            .await
+    }
+
+    async fn set_nx(&self, key: &str, value: &str) -> Result<bool> {
+        self.call("set_nx", key, self.db.set_nx(key, value)).await
+    }
+
+    async fn set_ex_nx(&self, key: &str, value: &str, ttl: &Duration) -> Result<bool> {
+        self.call("set_ex_nx", key, self.db.set_ex_nx(key, value, ttl))
+            .await
+    }
+
+    async fn del(&self, key: &str) -> Result<bool> {
+        self.call("del", key, self.db.del(key)).await
+    }
+
+    async fn del_if_eq(&self, key: &str, value: &str) -> Result<bool> {
+        self.call("del_if_eq", key, self.db.del_if_eq(key, value))
+            .await
+    }
+
+    async fn expire(&self, key: &str, ttl: i64) -> Result<bool> {
+        self.call("expire", key, self.db.expire(key, ttl)).await
+    }
+
+    async fn expire_if_eq(&self, key: &str, value: &str, ttl: &Duration) -> Result<bool> {
+        self.call("expire_if_eq", key, self.db.expire_if_eq(key, value, ttl))
+            .await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.call("flush", "", self.db.flush()).await
+    }
+
+    async fn keys(&self, key: &str) -> Result<Vec<String>> {
+        self.call("keys", key, self.db.keys(key)).await
+    }
+
+    async fn scan(&self, pattern: &str, count: usize) -> Result<KeyStream> {
+        self.call("scan", pattern, self.db.scan(pattern, count))
+            .await
+    }
+
+    async fn dels(&self, keys: &[String]) -> Result<u64> {
+        let key = keys.first().map_or("", String::as_str);
+        self.call("dels", key, self.db.dels(keys)).await
+    }
+
+    async fn mget(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        let key = keys.first().map_or("", String::as_str);
+        let ret = self.call("mget", key, self.db.mget(keys)).await?;
+        let hits = ret.iter().filter(|x| x.is_some()).count() as u64;
+        self.counters.hits.fetch_add(hits, Ordering::Relaxed);
+        self.counters
+            .misses
+            .fetch_add(ret.len() as u64 - hits, Ordering::Relaxed);
+        Ok(ret)
+    }
+
+    async fn exec(&self, tx: &Transaction) -> Result<Vec<Reply>> {
+        let key = tx.ops().first().map_or("", |x| x.key());
+        self.call("exec", key, self.db.exec(tx)).await
+    }
+
+    async fn dels_match(&self, pattern: &str) -> Result<u64> {
+        self.call("dels_match", pattern, self.db.dels_match(pattern))
+            .await
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<i64>> {
+        self.call("ttl", key, self.db.ttl(key)).await
+    }
+
+    async fn pttl(&self, key: &str) -> Result<Option<i64>> {
+        self.call("pttl", key, self.db.pttl(key)).await
+    }
+
+    async fn incr_by(&self, key: &str, delta: i64, ttl: Option<&Duration>) -> Result<i64> {
+        self.call("incr_by", key, self.db.incr_by(key, delta, ttl))
+            .await
+    }
+
+    async fn decr_by(&self, key: &str, delta: i64, ttl: Option<&Duration>) -> Result<i64> {
+        self.call("decr_by", key, self.db.decr_by(key, delta, ttl))
+            .await
+    }
+
+    async fn hset(&self, key: &str, field: &str, value: &str) -> Result<bool> {
+        self.call("hset", key, self.db.hset(key, field, value))
+            .await
+    }
+
+    async fn hget(&self, key: &str, field: &str) -> Result<Option<String>> {
+        self.lookup("hget", key, self.db.hget(key, field)).await
+    }
+
+    async fn hdel(&self, key: &str, field: &str) -> Result<bool> {
+        self.call("hdel", key, self.db.hdel(key, field)).await
+    }
+
+    async fn hgetall(&self, key: &str) -> Result<HashMap<String, String>> {
+        let ret = self
+            .lookup("hgetall", key, async {
+                let ret = self.db.hgetall(key).await?;
+                Ok(Some(ret).filter(|x| !x.is_empty()))
+            })
+            .await?;
+        Ok(ret.unwrap_or_default())
+    }
+
+    async fn hincr(&self, key: &str, field: &str, delta: i64) -> Result<i64> {
+        self.call("hincr", key, self.db.hincr(key, field, delta))
+            .await
+    }
+
+    async fn zadd(&self, key: &str, member: &str, score: f64) -> Result<bool> {
+        self.call("zadd", key, self.db.zadd(key, member, score))
+            .await
+    }
+
+    async fn zrem(&self, key: &str, member: &str) -> Result<bool> {
+        self.call("zrem", key, self.db.zrem(key, member)).await
+    }
+
+    async fn zrange_by_score(&self, key: &str, min: f64, max: f64) -> Result<Vec<(String, f64)>> {
+        self.call(
+            "zrange_by_score",
+            key,
+            self.db.zrange_by_score(key, min, max),
+        )
+        .await
+    }
+
+    async fn zrange_by_score_limit(
+        &self,
+        key: &str,
+        min: f64,
+        max: f64,
+        offset: usize,
+        count: usize,
+    ) -> Result<Vec<(String, f64)>> {
+        self.call(
+            "zrange_by_score_limit",
+            key,
+            self.db.zrange_by_score_limit(key, min, max, offset, count),
+        )
+        .await
+    }
+
+    async fn zrem_range_by_score(&self, key: &str, min: f64, max: f64) -> Result<u64> {
+        self.call(
+            "zrem_range_by_score",
+            key,
+            self.db.zrem_range_by_score(key, min, max),
+        )
+        .await
+    }
+
+    async fn zcard(&self, key: &str) -> Result<u64> {
+        self.call("zcard", key, self.db.zcard(key)).await
+    }
+
+    async fn publish(&self, channel: &str, message: &str) -> Result<u64> {
+        self.call("publish", channel, self.db.publish(channel, message))
+            .await
+    }
+
+    async fn subscribe(&self, pattern: &str) -> Result<MessageStream> {
+        self.call("subscribe", pattern, self.db.subscribe(pattern))
+            .await
+    }
+
+    async fn watch(&self, pattern: &str) -> Result<EventStream> {
+        self.call("watch", pattern, self.db.watch(pattern)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memorydb::default::{DefaultBackend, EvictionPolicy};
+
+    #[test]
+    fn test_key_pattern() {
+        assert_eq!(default_key_pattern("user:1"), "user:*");
+        assert_eq!(default_key_pattern("a:b:c"), "a:b:*");
+        assert_eq!(default_key_pattern("secret"), "*");
+    }
+
+    #[tokio::test]
+    async fn test_instrumented() {
+        let inner = Arc::new(
+            DefaultBackend::builder()
+                .capacity(1)
+                .eviction(EvictionPolicy::AllKeysLru)
+                .build()
+                .unwrap(),
+        );
+        let stats = inner.clone();
+        let db = Instrumented::new(inner).evictions(move || stats.stats().evicted);
+        let key = "_actix_cloud_ikey1";
+
+        db.set(key, "value").await.unwrap();
+        assert_eq!(db.get(key).await.unwrap().unwrap(), "value");
+        assert_eq!(db.get("_actix_cloud_ikey2").await.unwrap(), None);
+        assert!(db.hget(key, "field").await.is_err());
+        assert!(db.hgetall("_actix_cloud_ikey2").await.unwrap().is_empty());
+        db.set_ex("_actix_cloud_ikey2", "value", &Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(
+            db.metrics(),
+            Metrics {
+                hits: 1,
+                misses: 2,
+                errors: 1,
+                evictions: 1
+            }
+        );
+        assert_eq!(db.inner().stats().keys, 1);
+    }
+}
@@ -1,9 +1,187 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
+use anyhow::bail;
 use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt};
 
 use crate::Result;
 
+/// Message received from a subscribed channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub channel: String,
+    pub payload: String,
+}
+
+pub type MessageStream = BoxStream<'static, Message>;
+
+/// Keys returned by [`MemoryDB::scan`].
+pub type KeyStream = BoxStream<'static, Result<String>>;
+
+/// Kind of [`Event`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// The key is created or modified, including its TTL.
+    Set,
+    /// The key is deleted or evicted.
+    Del,
+    /// The key is removed after its TTL is reached.
+    Expired,
+}
+
+/// Change of a key received from [`MemoryDB::watch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub key: String,
+    pub kind: EventKind,
+}
+
+pub type EventStream = BoxStream<'static, Event>;
+
+/// Batch size of [`MemoryDB::dels_match`].
+const DELS_MATCH_BATCH: usize = 100;
+
+/// Command of a [`Transaction`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// Set the value with an optional TTL.
+    Set(String, Vec<u8>, Option<Duration>),
+    Del(String),
+    /// Set the TTL in seconds, the key is deleted if the TTL is not positive.
+    Expire(String, i64),
+    IncrBy(String, i64),
+    HSet(String, String, String),
+    HDel(String, String),
+    HIncr(String, String, i64),
+}
+
+impl Op {
+    pub fn key(&self) -> &str {
+        match self {
+            Op::Set(k, ..)
+            | Op::Del(k)
+            | Op::Expire(k, _)
+            | Op::IncrBy(k, _)
+            | Op::HSet(k, ..)
+            | Op::HDel(k, _)
+            | Op::HIncr(k, ..) => k,
+        }
+    }
+
+    fn key_mut(&mut self) -> &mut String {
+        match self {
+            Op::Set(k, ..)
+            | Op::Del(k)
+            | Op::Expire(k, _)
+            | Op::IncrBy(k, _)
+            | Op::HSet(k, ..)
+            | Op::HDel(k, _)
+            | Op::HIncr(k, ..) => k,
+        }
+    }
+}
+
+/// Reply of an [`Op`], in the same order as the transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    /// Reply of `Set`.
+    Ok,
+    /// Reply of `Del`, `Expire`, `HSet` and `HDel`, the same as the single key methods.
+    Bool(bool),
+    /// Reply of `IncrBy` and `HIncr`, the new value.
+    Int(i64),
+}
+
+/// Commands executed atomically by [`MemoryDB::exec`].
+///
+/// ```no_run
+/// # async fn f(db: std::sync::Arc<dyn actix_cloud::memorydb::MemoryDB>) -> actix_cloud::Result<()> {
+/// let ret = db
+///     .transaction(&|tx| {
+///         tx.set("key1", "value").incr_by("key2", 1);
+///     })
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transaction {
+    ops: Vec<Op>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> &mut Self {
+        self.set_bytes(key, value.as_bytes())
+    }
+
+    pub fn set_ex(&mut self, key: &str, value: &str, ttl: &Duration) -> &mut Self {
+        self.set_ex_bytes(key, value.as_bytes(), ttl)
+    }
+
+    pub fn set_bytes(&mut self, key: &str, value: &[u8]) -> &mut Self {
+        self.push(Op::Set(key.to_owned(), value.to_owned(), None))
+    }
+
+    pub fn set_ex_bytes(&mut self, key: &str, value: &[u8], ttl: &Duration) -> &mut Self {
+        self.push(Op::Set(key.to_owned(), value.to_owned(), Some(*ttl)))
+    }
+
+    pub fn del(&mut self, key: &str) -> &mut Self {
+        self.push(Op::Del(key.to_owned()))
+    }
+
+    pub fn expire(&mut self, key: &str, ttl: i64) -> &mut Self {
+        self.push(Op::Expire(key.to_owned(), ttl))
+    }
+
+    /// Unlike [`MemoryDB::incr_by`], no TTL is applied to new keys.
+    pub fn incr_by(&mut self, key: &str, delta: i64) -> &mut Self {
+        self.push(Op::IncrBy(key.to_owned(), delta))
+    }
+
+    pub fn hset(&mut self, key: &str, field: &str, value: &str) -> &mut Self {
+        self.push(Op::HSet(key.to_owned(), field.to_owned(), value.to_owned()))
+    }
+
+    pub fn hdel(&mut self, key: &str, field: &str) -> &mut Self {
+        self.push(Op::HDel(key.to_owned(), field.to_owned()))
+    }
+
+    pub fn hincr(&mut self, key: &str, field: &str, delta: i64) -> &mut Self {
+        self.push(Op::HIncr(key.to_owned(), field.to_owned(), delta))
+    }
+
+    pub fn push(&mut self, op: Op) -> &mut Self {
+        self.ops.push(op);
+        self
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Copy of the transaction with every key mapped by `f`, used by wrappers.
+    pub fn map_keys<F>(&self, f: F) -> Self
+    where
+        F: Fn(&str) -> String,
+    {
+        let mut ret = self.clone();
+        for i in &mut ret.ops {
+            let key = f(i.key());
+            *i.key_mut() = key;
+        }
+        ret
+    }
+}
+
 #[async_trait]
 pub trait MemoryDB: Send + Sync {
     async fn set(&self, key: &str, value: &str) -> Result<()>;
@@ -11,165 +189,308 @@ pub trait MemoryDB: Send + Sync {
     async fn get_del(&self, key: &str) -> Result<Option<String>>;
     async fn get_ex(&self, key: &str, ttl: &Duration) -> Result<Option<String>>;
     async fn set_ex(&self, key: &str, value: &str, ttl: &Duration) -> Result<()>;
+    /// Binary-safe variant of [`MemoryDB::set`].
+    async fn set_bytes(&self, key: &str, value: &[u8]) -> Result<()>;
+    /// Binary-safe variant of [`MemoryDB::get`].
+    async fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    /// Binary-safe variant of [`MemoryDB::get_del`].
+    async fn get_del_bytes(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    /// Binary-safe variant of [`MemoryDB::get_ex`].
+    async fn get_ex_bytes(&self, key: &str, ttl: &Duration) -> Result<Option<Vec<u8>>>;
+    /// Binary-safe variant of [`MemoryDB::set_ex`].
+    async fn set_ex_bytes(&self, key: &str, value: &[u8], ttl: &Duration) -> Result<()>;
+    /// Set `key` only if it does not exist. Return `true` if the value is set.
+    async fn set_nx(&self, key: &str, value: &str) -> Result<bool>;
+    /// Set `key` with `ttl` only if it does not exist. Return `true` if the value is set.
+    async fn set_ex_nx(&self, key: &str, value: &str, ttl: &Duration) -> Result<bool>;
     async fn del(&self, key: &str) -> Result<bool>;
+    /// Atomically delete `key` only if its value equals to `value`.
+    async fn del_if_eq(&self, key: &str, value: &str) -> Result<bool>;
     async fn expire(&self, key: &str, ttl: i64) -> Result<bool>;
+    /// Atomically set the TTL of `key` only if its value equals to `value`.
+    async fn expire_if_eq(&self, key: &str, value: &str, ttl: &Duration) -> Result<bool>;
     async fn flush(&self) -> Result<()>;
+    /// Get all keys matching the glob-style pattern `key` at once.
+    ///
+    /// This blocks Redis until all keys are collected, prefer [`MemoryDB::scan`] for large databases.
     async fn keys(&self, key: &str) -> Result<Vec<String>>;
+    /// Incrementally iterate keys matching the glob-style `pattern`, `count` is a hint of the batch size.
+    ///
+    /// Keys existing during the whole iteration are returned, keys added or removed during the
+    /// iteration may or may not be returned. The same key may be returned more than once.
+    async fn scan(&self, pattern: &str, count: usize) -> Result<KeyStream>;
     async fn dels(&self, keys: &[String]) -> Result<u64>;
+    /// Get the values of `keys` at once, `None` for missing keys.
+    async fn mget(&self, keys: &[String]) -> Result<Vec<Option<String>>>;
+    /// Atomically set multiple keys.
+    async fn mset(&self, items: &[(String, String)]) -> Result<()> {
+        let mut tx = Transaction::new();
+        for (k, v) in items {
+            tx.set(k, v);
+        }
+        self.exec(&tx).await?;
+        Ok(())
+    }
+    /// Atomically set multiple keys with the same `ttl`.
+    async fn mset_ex(&self, items: &[(String, String)], ttl: &Duration) -> Result<()> {
+        let mut tx = Transaction::new();
+        for (k, v) in items {
+            tx.set_ex(k, v, ttl);
+        }
+        self.exec(&tx).await?;
+        Ok(())
+    }
+    /// Execute all commands of `tx` atomically, other clients never observe a partially applied transaction.
+    ///
+    /// The same as Redis `MULTI`/`EXEC`, a failed command does not roll back the others,
+    /// the first error is returned after all commands are executed.
+    async fn exec(&self, tx: &Transaction) -> Result<Vec<Reply>>;
+    /// Build a transaction with `f` and execute it with [`MemoryDB::exec`].
+    async fn transaction(
+        &self,
+        f: &(dyn for<'a> Fn(&'a mut Transaction) + Send + Sync),
+    ) -> Result<Vec<Reply>> {
+        let mut tx = Transaction::new();
+        f(&mut tx);
+        self.exec(&tx).await
+    }
+    /// Delete all keys matching the glob-style `pattern` in batches. Return the number of deleted keys.
+    async fn dels_match(&self, pattern: &str) -> Result<u64> {
+        let mut keys = self
+            .scan(pattern, DELS_MATCH_BATCH)
+            .await?
+            .chunks(DELS_MATCH_BATCH);
+        let mut sum = 0;
+        while let Some(x) = keys.next().await {
+            let x = x.into_iter().collect::<Result<Vec<_>>>()?;
+            sum += self.dels(&x).await?;
+        }
+        Ok(sum)
+    }
+    /// Get the TTL of `key` in seconds, rounded to the nearest second.
+    ///
+    /// Return `Some(0)` if `key` expires in less than half a second.
     async fn ttl(&self, key: &str) -> Result<Option<i64>>;
+    /// Get the TTL of `key` in milliseconds.
+    async fn pttl(&self, key: &str) -> Result<Option<i64>>;
+
+    /// Atomically increase the integer stored at `key` by `delta` and return the new value.
+    ///
+    /// If `key` does not exist, it is created with `delta` and `ttl` is applied.
+    /// The TTL of an existing key is left unchanged.
+    async fn incr_by(&self, key: &str, delta: i64, ttl: Option<&Duration>) -> Result<i64>;
+
+    /// Atomically decrease the integer stored at `key` by `delta` and return the new value.
+    ///
+    /// If `key` does not exist, it is created with `-delta` and `ttl` is applied.
+    /// The TTL of an existing key is left unchanged.
+    async fn decr_by(&self, key: &str, delta: i64, ttl: Option<&Duration>) -> Result<i64>;
+
+    /// Set `field` in the hash stored at `key`. Return `true` if `field` is newly created.
+    ///
+    /// The hash can be expired as a whole using `expire`.
+    async fn hset(&self, key: &str, field: &str, value: &str) -> Result<bool>;
+    async fn hget(&self, key: &str, field: &str) -> Result<Option<String>>;
+    /// Remove `field` from the hash stored at `key`. The key is deleted when the hash is empty.
+    async fn hdel(&self, key: &str, field: &str) -> Result<bool>;
+    async fn hgetall(&self, key: &str) -> Result<HashMap<String, String>>;
+    /// Atomically increase the integer stored at `field` of the hash by `delta`.
+    async fn hincr(&self, key: &str, field: &str, delta: i64) -> Result<i64>;
+
+    /// Add `member` with `score` to the sorted set stored at `key`, or update its score.
+    /// Return `true` if `member` is newly added.
+    ///
+    /// The sorted set can be expired as a whole using `expire`.
+    async fn zadd(&self, key: &str, member: &str, score: f64) -> Result<bool>;
+    /// Remove `member` from the sorted set stored at `key`. The key is deleted when the set is empty.
+    async fn zrem(&self, key: &str, member: &str) -> Result<bool>;
+    /// Members with score in `[min, max]` and their scores, ordered by score then by member.
+    ///
+    /// Use [`f64::NEG_INFINITY`] and [`f64::INFINITY`] for unbounded ranges.
+    async fn zrange_by_score(&self, key: &str, min: f64, max: f64) -> Result<Vec<(String, f64)>>;
+    /// Same as [`MemoryDB::zrange_by_score`], but skip the first `offset` members and return at most `count`.
+    ///
+    /// The default implementation reads the whole range, backends should override it.
+    async fn zrange_by_score_limit(
+        &self,
+        key: &str,
+        min: f64,
+        max: f64,
+        offset: usize,
+        count: usize,
+    ) -> Result<Vec<(String, f64)>> {
+        let ret = self.zrange_by_score(key, min, max).await?;
+        Ok(ret.into_iter().skip(offset).take(count).collect())
+    }
+    /// Remove members with score in `[min, max]`. Return the number of removed members.
+    async fn zrem_range_by_score(&self, key: &str, min: f64, max: f64) -> Result<u64>;
+    /// Number of members in the sorted set stored at `key`.
+    async fn zcard(&self, key: &str) -> Result<u64>;
+
+    /// Publish `message` to `channel`. Return the number of subscribers that received it.
+    async fn publish(&self, channel: &str, message: &str) -> Result<u64>;
+    /// Subscribe to all channels matching the glob-style `pattern`.
+    ///
+    /// The subscription is cancelled when the stream is dropped.
+    async fn subscribe(&self, pattern: &str) -> Result<MessageStream>;
+
+    /// Watch changes of all keys matching the glob-style `pattern`.
+    ///
+    /// Expired events are generated when expired keys are removed, which may be later than the TTL.
+    /// `flush` does not generate events, and a command may generate more than one event.
+    /// Events may be dropped if the stream is not consumed fast enough.
+    ///
+    /// The watch is cancelled when the stream is dropped.
+    /// Backends do not support it by default.
+    async fn watch(&self, _pattern: &str) -> Result<EventStream> {
+        bail!("Keyspace notifications are not supported")
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
-    use tokio::time::sleep;
 
     use super::*;
-    use crate::memorydb::default::DefaultBackend;
-
-    #[cfg(feature = "redis")]
-    async fn setup_redis() -> impl MemoryDB {
-        crate::memorydb::redis::RedisBackend::new("redis://127.0.0.1:6379/0")
-            .await
-            .unwrap()
-    }
-
-    fn setup_default() -> impl MemoryDB {
-        DefaultBackend::new(None)
+    use crate::memorydb::{
+        clock::MockClock,
+        default::{DefaultBackend, EvictionPolicy, Stats},
+        testing,
+    };
+
+    #[cfg(feature = "persist")]
+    use crate::memorydb::default::Fsync;
+
+    fn setup_default() -> (DefaultBackend, testing::Time) {
+        let clock = MockClock::new();
+        let r = DefaultBackend::builder()
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        (r, testing::Time::Mock(clock))
     }
 
     #[tokio::test]
     async fn test_normal() {
-        test_normal_fn("default", setup_default()).await;
-        #[cfg(feature = "redis")]
-        test_normal_fn("redis", setup_redis().await).await;
+        testing::normal(&setup_default().0).await;
     }
 
-    async fn test_normal_fn(name: &str, r: impl MemoryDB) {
-        let key = "_actix_cloud_key1";
-        let value1 = "value1";
-        let value2 = "value2";
-
-        println!("Backend: {}", name);
-
-        let _ = r.del(key).await;
-
-        assert_eq!(r.get(key).await.unwrap(), None);
-
-        r.set(key, value1).await.unwrap();
-        assert_eq!(r.get(key).await.unwrap().unwrap(), value1);
-        r.set(key, value2).await.unwrap();
-        assert_eq!(r.get(key).await.unwrap().unwrap(), value2);
-
-        assert_eq!(r.del(key).await.unwrap(), true);
-        assert_eq!(r.del(key).await.unwrap(), false);
-        assert_eq!(r.get(key).await.unwrap(), None);
+    #[tokio::test]
+    async fn test_bytes() {
+        let (r, time) = setup_default();
+        testing::bytes(&r, &time).await;
     }
 
     #[tokio::test]
     async fn test_ex() {
-        test_ex_fn("default", setup_default()).await;
-        #[cfg(feature = "redis")]
-        test_ex_fn("redis", setup_redis().await).await;
+        let (r, time) = setup_default();
+        testing::ex(&r, &time).await;
     }
 
-    async fn test_ex_fn(name: &str, r: impl MemoryDB) {
-        let key = "_actix_cloud_key2";
-        let value = "value";
-
-        println!("Backend: {}", name);
+    #[tokio::test]
+    async fn test_ex_ms() {
+        let (r, time) = setup_default();
+        testing::ex_ms(&r, &time).await;
+    }
 
-        let _ = r.del(key).await;
+    #[tokio::test]
+    async fn test_expire() {
+        let (r, time) = setup_default();
+        testing::expire(&r, &time).await;
+    }
 
-        r.set(key, value).await.unwrap();
-        assert_eq!(r.ttl(key).await.unwrap(), None);
-        assert_eq!(r.get_del(key).await.unwrap().unwrap(), value);
-        assert_eq!(r.get(key).await.unwrap(), None);
+    #[tokio::test]
+    async fn test_batch() {
+        testing::batch(&setup_default().0).await;
+    }
 
-        r.set_ex(key, value, &Duration::from_secs(2)).await.unwrap();
-        assert_eq!(r.get(key).await.unwrap().unwrap(), value);
-        assert_eq!(r.ttl(key).await.unwrap(), Some(2));
-        sleep(Duration::from_secs(1)).await;
-        assert_eq!(r.ttl(key).await.unwrap(), Some(1));
-        assert_eq!(
-            r.get_ex(key, &Duration::from_secs(2))
-                .await
-                .unwrap()
-                .unwrap(),
-            value
-        );
-        assert_eq!(r.ttl(key).await.unwrap(), Some(2));
-        sleep(Duration::from_secs(1)).await;
-        assert_eq!(r.ttl(key).await.unwrap(), Some(1));
-        assert_eq!(r.get(key).await.unwrap().unwrap(), value);
-        sleep(Duration::from_secs(2)).await;
-        assert_eq!(r.ttl(key).await.unwrap(), None);
-        assert_eq!(r.get(key).await.unwrap(), None);
+    #[tokio::test]
+    async fn test_transaction() {
+        testing::transaction(&setup_default().0).await;
     }
 
     #[tokio::test]
-    async fn test_expire() {
-        test_expire_fn("default", setup_default()).await;
-        #[cfg(feature = "redis")]
-        test_expire_fn("redis", setup_redis().await).await;
+    async fn test_transaction_full() {
+        let r = DefaultBackend::builder()
+            .capacity(1)
+            .eviction(EvictionPolicy::NoEviction)
+            .build()
+            .unwrap();
+        testing::transaction_full(&r).await;
     }
 
-    async fn test_expire_fn(name: &str, r: impl MemoryDB) {
-        let key = "_actix_cloud_key3";
-        let value = "value";
+    #[tokio::test]
+    async fn test_scan() {
+        testing::scan(&setup_default().0).await;
+    }
 
-        println!("Backend: {}", name);
+    #[tokio::test]
+    async fn test_cond() {
+        let (r, time) = setup_default();
+        testing::cond(&r, &time).await;
+    }
 
-        let _ = r.del(key).await;
+    #[tokio::test]
+    async fn test_incr() {
+        let (r, time) = setup_default();
+        testing::incr(&r, &time).await;
+    }
 
-        r.set(key, value).await.unwrap();
-        assert_eq!(r.get(key).await.unwrap().unwrap(), value);
-        assert_eq!(r.expire(key, 1).await.unwrap(), true);
-        sleep(Duration::from_secs(2)).await;
-        assert_eq!(r.get(key).await.unwrap(), None);
-        assert_eq!(r.expire(key, 1).await.unwrap(), false);
+    #[tokio::test]
+    async fn test_hash() {
+        let (r, time) = setup_default();
+        testing::hash(&r, &time).await;
+    }
 
-        r.set_ex(key, value, &Duration::from_secs(1)).await.unwrap();
-        assert_eq!(r.expire(key, 3).await.unwrap(), true);
-        sleep(Duration::from_secs(2)).await;
-        assert_eq!(r.get(key).await.unwrap().unwrap(), value);
-        assert_eq!(r.expire(key, -1).await.unwrap(), true);
-        assert_eq!(r.get(key).await.unwrap(), None);
-        assert_eq!(r.expire(key, 0).await.unwrap(), false);
+    #[tokio::test]
+    async fn test_zset() {
+        testing::zset(&setup_default().0).await;
     }
 
     #[tokio::test]
-    async fn test_batch() {
-        test_batch_fn("default", setup_default()).await;
-        #[cfg(feature = "redis")]
-        test_batch_fn("redis", setup_redis().await).await;
+    async fn test_zset_limit() {
+        testing::zset_limit(&setup_default().0).await;
     }
 
-    async fn test_batch_fn(name: &str, r: impl MemoryDB) {
-        let key1 = "_actix_cloud_bkey1";
-        let key2 = "_actix_cloud_bkey2";
-        let value = "value";
+    #[tokio::test]
+    async fn test_flush() {
+        testing::flush(&setup_default().0).await;
+    }
 
-        println!("Backend: {}", name);
+    #[tokio::test]
+    async fn test_watch() {
+        let (r, time) = setup_default();
+        testing::watch(&r, &time).await;
+    }
 
-        let _ = r.del(key1).await;
-        let _ = r.del(key2).await;
+    #[tokio::test]
+    async fn test_pubsub() {
+        testing::pubsub(&setup_default().0).await;
+    }
 
-        r.set(key1, value).await.unwrap();
-        r.set(key2, value).await.unwrap();
-        let ret = r.keys("_actix_cl?ud_bkey*").await.unwrap();
-        assert_eq!(ret.len(), 2);
-        assert_eq!(
-            (ret[0] == key1 && ret[1] == key2) || (ret[1] == key1 && ret[0] == key2),
-            true
-        );
-        assert_eq!(
-            r.dels(&vec![key1.to_owned(), key2.to_owned()])
-                .await
-                .unwrap(),
-            2
-        );
-        assert_eq!(r.keys("_actix_cl?ud_bkey*").await.unwrap().len(), 0);
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    #[ignore = "requires redis-server"]
+    async fn test_redis() {
+        let r = crate::memorydb::redis::RedisBackend::new("redis://127.0.0.1:6379/0")
+            .await
+            .unwrap();
+        let time = testing::Time::System;
+        testing::normal(&r).await;
+        testing::bytes(&r, &time).await;
+        testing::ex(&r, &time).await;
+        testing::ex_ms(&r, &time).await;
+        testing::expire(&r, &time).await;
+        testing::batch(&r).await;
+        testing::transaction(&r).await;
+        testing::scan(&r).await;
+        testing::cond(&r, &time).await;
+        testing::incr(&r, &time).await;
+        testing::hash(&r, &time).await;
+        testing::zset(&r).await;
+        testing::zset_limit(&r).await;
+        testing::watch(&r, &time).await;
+        testing::pubsub(&r).await;
     }
 
     #[tokio::test]
@@ -178,55 +499,251 @@ mod tests {
         let key2 = "_actix_cloud_ckey2";
         let key3 = "_actix_cloud_ckey3";
         let value = "value";
+        let clock = MockClock::new();
 
-        let r = DefaultBackend::new(Some(2));
+        let r = DefaultBackend::builder()
+            .capacity(2)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
         let _ = r.del(key1).await;
         let _ = r.del(key2).await;
         let _ = r.del(key3).await;
 
-        assert_eq!(r.set(key1, value).await.is_ok(), true);
-        assert_eq!(r.set(key2, value).await.is_ok(), true);
-        assert_eq!(r.set(key3, value).await.is_ok(), false);
-        assert_eq!(r.set(key1, value).await.is_ok(), true);
-        assert_eq!(r.set(key2, value).await.is_ok(), true);
+        assert!(r.set(key1, value).await.is_ok());
+        assert!(r.set(key2, value).await.is_ok());
+        assert!(r.set(key3, value).await.is_err());
+        assert!(r.set(key1, value).await.is_ok());
+        assert!(r.set(key2, value).await.is_ok());
 
-        assert_eq!(
-            r.set_ex(key2, value, &Duration::from_secs(1)).await.is_ok(),
-            true
-        );
-        sleep(Duration::from_secs(2)).await;
-        assert_eq!(r.set(key3, value).await.is_ok(), true);
+        assert!(r.set_ex(key2, value, &Duration::from_secs(1)).await.is_ok());
+        clock.advance(Duration::from_secs(2));
+        assert!(r.set(key3, value).await.is_ok());
         assert_eq!(r.get(key1).await.unwrap().unwrap(), value);
         assert_eq!(r.get(key2).await.unwrap(), None);
 
-        assert_eq!(
-            r.set_ex(key3, value, &Duration::from_secs(3)).await.is_ok(),
-            true
-        );
-        sleep(Duration::from_secs(1)).await;
-        assert_eq!(r.set(key2, value).await.is_ok(), true);
+        assert!(r.set_ex(key3, value, &Duration::from_secs(3)).await.is_ok());
+        clock.advance(Duration::from_secs(1));
+        assert!(r.set(key2, value).await.is_ok());
         assert_eq!(r.get(key1).await.unwrap().unwrap(), value);
         assert_eq!(r.get(key3).await.unwrap(), None);
 
-        assert_eq!(r.del(key1).await.unwrap(), true);
-        assert_eq!(r.del(key2).await.unwrap(), true);
-        assert_eq!(
-            r.set_ex(key1, value, &Duration::from_secs(3)).await.is_ok(),
-            true
-        );
+        assert!(r.del(key1).await.unwrap());
+        assert!(r.del(key2).await.unwrap());
+        assert!(r.set_ex(key1, value, &Duration::from_secs(3)).await.is_ok());
+        assert!(r.set_ex(key2, value, &Duration::from_secs(2)).await.is_ok());
+        assert!(r.set_ex(key3, value, &Duration::from_secs(2)).await.is_ok());
+        assert_eq!(r.get(key1).await.unwrap().unwrap(), value);
+        assert_eq!(r.get(key2).await.unwrap(), None);
+        assert_eq!(r.get(key3).await.unwrap().unwrap(), value);
+
+        assert!(r.del(key1).await.unwrap());
+        assert!(r.del(key3).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_default_eviction() {
+        let key1 = "_actix_cloud_ekey1";
+        let key2 = "_actix_cloud_ekey2";
+        let key3 = "_actix_cloud_ekey3";
+        let value = "value";
+        let ttl = Duration::from_secs(10);
+        let clock = MockClock::new();
+        let tick = || clock.advance(Duration::from_millis(10));
+        let build = |eviction| {
+            DefaultBackend::builder()
+                .capacity(2)
+                .eviction(eviction)
+                .clock(clock.clone())
+                .build()
+                .unwrap()
+        };
+
+        let r = build(EvictionPolicy::NoEviction);
+        r.set_ex(key1, value, &Duration::from_millis(100))
+            .await
+            .unwrap();
+        r.set_ex(key2, value, &ttl).await.unwrap();
+        assert!(r.set(key3, value).await.is_err());
+        clock.advance(Duration::from_millis(200));
+        r.set(key3, value).await.unwrap();
+        assert!(r.set(key1, value).await.is_err());
+
+        let r = build(EvictionPolicy::AllKeysLru);
+        r.set(key1, value).await.unwrap();
+        tick();
+        r.set(key2, value).await.unwrap();
+        tick();
+        r.get(key1).await.unwrap();
+        r.set(key3, value).await.unwrap();
+        assert_eq!(r.get(key1).await.unwrap().unwrap(), value);
+        assert_eq!(r.get(key2).await.unwrap(), None);
+
+        let r = build(EvictionPolicy::AllKeysLfu);
+        r.set(key1, value).await.unwrap();
+        r.get(key1).await.unwrap();
+        r.get(key1).await.unwrap();
+        r.set(key2, value).await.unwrap();
+        r.set(key3, value).await.unwrap();
+        assert_eq!(r.get(key1).await.unwrap().unwrap(), value);
+        assert_eq!(r.get(key2).await.unwrap(), None);
+
+        let r = build(EvictionPolicy::VolatileLru);
+        r.set_ex(key1, value, &ttl).await.unwrap();
+        tick();
+        r.set(key2, value).await.unwrap();
+        tick();
+        r.set_ex(key3, value, &ttl).await.unwrap();
+        assert_eq!(r.get(key1).await.unwrap(), None);
+        tick();
+        r.get(key3).await.unwrap();
+        assert!(r.set(key1, value).await.is_ok());
+        assert_eq!(r.get(key3).await.unwrap(), None);
+        assert!(r.set(key3, value).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_default_memory() {
+        let key1 = "_actix_cloud_mkey1";
+        let key2 = "_actix_cloud_mkey2";
+        let key3 = "_actix_cloud_mkey3";
+        let value = "value";
+
+        let r = DefaultBackend::new(None);
+        r.set(key1, value).await.unwrap();
+        let size = r.stats().memory;
+        r.set(key2, value).await.unwrap();
+        assert_eq!(r.stats().memory, size * 2);
+        r.del(key2).await.unwrap();
+        assert_eq!(r.stats().memory, size);
+        r.hset(key2, "field", value).await.unwrap();
+        assert!(r.stats().memory > size * 2);
+        r.flush().await.unwrap();
+        assert_eq!(r.stats().memory, 0);
+
+        let clock = MockClock::new();
+        let r = DefaultBackend::builder()
+            .max_memory(size * 5 / 2)
+            .eviction(EvictionPolicy::AllKeysLru)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        r.set(key1, value).await.unwrap();
+        clock.advance(Duration::from_millis(10));
+        r.set(key2, value).await.unwrap();
+        r.set(key3, value).await.unwrap();
+        assert_eq!(r.get(key1).await.unwrap(), None);
         assert_eq!(
-            r.set_ex(key2, value, &Duration::from_secs(2)).await.is_ok(),
-            true
+            r.stats(),
+            Stats {
+                keys: 2,
+                memory: size * 2,
+                evicted: 1
+            }
         );
+        assert!(r.set(key1, &value.repeat(size)).await.is_err());
+        r.hset(key1, "field", value).await.unwrap();
+        assert_eq!(r.stats().keys, 1);
+        assert!(r.stats().memory <= size * 5 / 2);
+        assert!(r.hset(key1, "field", &value.repeat(size)).await.is_err());
+        assert!(!r.set_nx(key1, &value.repeat(size)).await.unwrap());
+        assert!(r.zadd(key2, &value.repeat(size), 1.0).await.is_err());
+        assert!(r.stats().memory <= size * 5 / 2);
+    }
+
+    #[cfg(feature = "persist")]
+    #[tokio::test]
+    async fn test_default_persist() {
+        let key1 = "_actix_cloud_pkey1";
+        let key2 = "_actix_cloud_pkey2";
+        let key3 = "_actix_cloud_pkey3";
+        let key4 = "_actix_cloud_pkey4";
+        let key5 = "_actix_cloud_pkey5";
+        let value = "value";
+        let name = format!("_actix_cloud_persist_{}", std::process::id());
+        let snapshot = std::env::temp_dir().join(format!("{name}.json"));
+        let aof = std::env::temp_dir().join(format!("{name}.aof"));
+        let _ = std::fs::remove_file(&snapshot);
+        let _ = std::fs::remove_file(&aof);
+        let clock = MockClock::new();
+        let build = || {
+            DefaultBackend::builder()
+                .snapshot(&snapshot)
+                .append_only(&aof)
+                .fsync(Fsync::Always)
+                .clock(clock.clone())
+                .build()
+                .unwrap()
+        };
+        // Drop `r` but keep the files as they were, skipping the snapshot on drop.
+        let crash = |r: DefaultBackend| {
+            r.sync().unwrap();
+            let files = [&snapshot, &aof].map(|x| std::fs::read(x).ok());
+            drop(r);
+            for (path, data) in [&snapshot, &aof].into_iter().zip(files) {
+                match data {
+                    Some(x) => std::fs::write(path, x).unwrap(),
+                    None => std::fs::remove_file(path).unwrap(),
+                }
+            }
+        };
+
+        let r = build();
+        r.set(key1, value).await.unwrap();
+        r.set_ex(key2, value, &Duration::from_secs(10))
+            .await
+            .unwrap();
+        r.set_ex(key3, value, &Duration::from_millis(200))
+            .await
+            .unwrap();
+        r.hset(key1, "f1", value).await.unwrap_err();
+        r.del(key1).await.unwrap();
+        r.hset(key1, "f1", value).await.unwrap();
+        r.set_bytes(key4, &[0, 255]).await.unwrap();
+        r.zadd(key5, "m1", f64::NEG_INFINITY).await.unwrap();
+        r.zadd(key5, "m2", 1.5).await.unwrap();
+        // Crash without snapshot, recover from the log.
+        crash(r);
+        assert!(!snapshot.exists());
+        clock.advance(Duration::from_millis(300));
+
+        let r = build();
+        assert_eq!(r.hget(key1, "f1").await.unwrap().unwrap(), value);
+        assert_eq!(r.get(key2).await.unwrap().unwrap(), value);
+        assert_eq!(r.ttl(key2).await.unwrap(), Some(10));
+        assert_eq!(r.get(key3).await.unwrap(), None);
+        assert_eq!(r.get_bytes(key4).await.unwrap().unwrap(), [0, 255]);
         assert_eq!(
-            r.set_ex(key3, value, &Duration::from_secs(2)).await.is_ok(),
-            true
+            r.zrange_by_score(key5, f64::NEG_INFINITY, f64::INFINITY)
+                .await
+                .unwrap(),
+            [("m1".to_owned(), f64::NEG_INFINITY), ("m2".to_owned(), 1.5)]
         );
+        r.save().unwrap();
+        assert_eq!(std::fs::metadata(&aof).unwrap().len(), 0);
+        r.set(key3, value).await.unwrap();
+        r.flush().await.unwrap();
+        r.set(key1, value).await.unwrap();
+        r.set_bytes(key4, &[0, 255]).await.unwrap();
+        r.zadd(key5, "m1", f64::INFINITY).await.unwrap();
+        drop(r);
+
+        let r = DefaultBackend::builder()
+            .snapshot(&snapshot)
+            .build()
+            .unwrap();
         assert_eq!(r.get(key1).await.unwrap().unwrap(), value);
         assert_eq!(r.get(key2).await.unwrap(), None);
-        assert_eq!(r.get(key3).await.unwrap().unwrap(), value);
+        assert_eq!(r.get(key3).await.unwrap(), None);
+        assert_eq!(r.get_bytes(key4).await.unwrap().unwrap(), [0, 255]);
+        assert_eq!(
+            r.zrange_by_score(key5, 0.0, f64::INFINITY).await.unwrap(),
+            [("m1".to_owned(), f64::INFINITY)]
+        );
+        drop(r);
 
-        assert_eq!(r.del(key1).await.unwrap(), true);
-        assert_eq!(r.del(key3).await.unwrap(), true);
+        assert!(DefaultBackend::builder().append_only(&aof).build().is_err());
+        std::fs::remove_file(&snapshot).unwrap();
+        std::fs::remove_file(&aof).unwrap();
     }
 }
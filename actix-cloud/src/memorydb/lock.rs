@@ -0,0 +1,169 @@
+use std::{sync::Arc, time::Duration};
+
+use rand::distr::{Alphanumeric, SampleString as _};
+use tokio::time::{sleep, Instant};
+
+use super::interface::MemoryDB;
+use crate::Result;
+
+/// Distributed lock built on top of [`MemoryDB`].
+///
+/// The lock is held for a lease of `ttl` and must be renewed before it expires.
+/// Each holder owns a random token, so it can never renew or release a lock acquired by others.
+///
+/// Dropping the guard does not release the lock, the lease will expire after `ttl`.
+/// Use [`Lock::release`] to release it immediately.
+pub struct Lock {
+    db: Arc<dyn MemoryDB>,
+    key: String,
+    token: String,
+    ttl: Duration,
+}
+
+impl Lock {
+    /// Try to acquire the lock once. Return `None` if the lock is held by others.
+    pub async fn try_acquire<S>(
+        db: Arc<dyn MemoryDB>,
+        key: S,
+        ttl: Duration,
+    ) -> Result<Option<Self>>
+    where
+        S: Into<String>,
+    {
+        let key = key.into();
+        let token = Alphanumeric.sample_string(&mut rand::rng(), 32);
+        if db.set_ex_nx(&key, &token, &ttl).await? {
+            Ok(Some(Self {
+                db,
+                key,
+                token,
+                ttl,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Acquire the lock, retry every `interval` until `timeout` is reached.
+    /// Return `None` if the lock cannot be acquired in time.
+    pub async fn acquire<S>(
+        db: Arc<dyn MemoryDB>,
+        key: S,
+        ttl: Duration,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Result<Option<Self>>
+    where
+        S: Into<String>,
+    {
+        let key = key.into();
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(x) = Self::try_acquire(db.clone(), key.clone(), ttl).await? {
+                return Ok(Some(x));
+            }
+            if Instant::now() + interval > deadline {
+                return Ok(None);
+            }
+            sleep(interval).await;
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Random token identifying the holder.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Lease TTL of the lock.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Extend the lease to `ttl` from now. Return `false` if the lock is lost.
+    pub async fn renew(&self) -> Result<bool> {
+        self.db
+            .expire_if_eq(&self.key, &self.token, &self.ttl)
+            .await
+    }
+
+    /// Release the lock. Return `false` if the lock is already lost.
+    pub async fn release(self) -> Result<bool> {
+        self.db.del_if_eq(&self.key, &self.token).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memorydb::{clock::MockClock, default::DefaultBackend};
+
+    // Retries run on paused time, leases expire on the mock clock.
+    #[tokio::test(start_paused = true)]
+    async fn test_lock() {
+        let key = "_actix_cloud_lock1";
+        let clock = MockClock::new();
+        let db: Arc<dyn MemoryDB> = Arc::new(
+            DefaultBackend::builder()
+                .clock(clock.clone())
+                .build()
+                .unwrap(),
+        );
+        let ttl = Duration::from_secs(2);
+
+        let lock = Lock::try_acquire(db.clone(), key, ttl)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(db.get(key).await.unwrap().unwrap(), lock.token());
+        assert!(Lock::try_acquire(db.clone(), key, ttl)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(Lock::acquire(
+            db.clone(),
+            key,
+            ttl,
+            Duration::from_millis(100),
+            Duration::from_millis(300)
+        )
+        .await
+        .unwrap()
+        .is_none());
+
+        assert!(lock.renew().await.unwrap());
+        assert_eq!(db.ttl(key).await.unwrap(), Some(2));
+        assert!(lock.release().await.unwrap());
+
+        let lock = Lock::try_acquire(db.clone(), key, Duration::from_secs(1))
+            .await
+            .unwrap()
+            .unwrap();
+        // The lease expires while `other` is retrying.
+        tokio::spawn({
+            let clock = clock.clone();
+            async move {
+                sleep(Duration::from_secs(1)).await;
+                clock.advance(Duration::from_secs(1));
+            }
+        });
+        let other = Lock::acquire(
+            db.clone(),
+            key,
+            ttl,
+            Duration::from_millis(100),
+            Duration::from_secs(3),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(!lock.renew().await.unwrap());
+        assert!(!lock.release().await.unwrap());
+        assert_eq!(db.get(key).await.unwrap().unwrap(), other.token());
+        assert!(other.release().await.unwrap());
+        assert_eq!(db.get(key).await.unwrap(), None);
+    }
+}
@@ -1,6 +1,20 @@
 pub mod interface;
 pub use interface::MemoryDB;
 
+pub mod clock;
 pub mod default;
+#[cfg(feature = "serde")]
+pub mod ext;
+#[cfg(feature = "logger")]
+pub mod instrumented;
+pub mod lock;
+pub mod namespaced;
+#[cfg(feature = "persist")]
+mod persist;
 #[cfg(feature = "redis")]
 pub mod redis;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(any(test, feature = "memorydb-testing"))]
+pub mod testing;
+pub mod tiered;
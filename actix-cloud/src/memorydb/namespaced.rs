@@ -0,0 +1,386 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use futures::{future, StreamExt};
+
+use super::interface::{
+    Event, EventStream, KeyStream, MemoryDB, Message, MessageStream, Reply, Transaction,
+};
+use crate::Result;
+
+/// [`MemoryDB`] wrapper scoping all keys and channels to a namespace.
+///
+/// Keys and channels are transparently prefixed with `prefix`, and the prefix is stripped
+/// from `keys`, `scan` and `subscribe` results. `flush` only deletes keys in the namespace.
+///
+/// ```no_run
+/// # fn f(db: std::sync::Arc<dyn actix_cloud::memorydb::MemoryDB>) {
+/// use actix_cloud::memorydb::namespaced::Namespaced;
+///
+/// let tenant = Namespaced::new(db, "tenant1:");
+/// # }
+/// ```
+pub struct Namespaced<M: ?Sized> {
+    db: Arc<M>,
+    prefix: String,
+}
+
+impl<M: ?Sized> Clone for Namespaced<M> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            prefix: self.prefix.clone(),
+        }
+    }
+}
+
+impl<M: MemoryDB + ?Sized> Namespaced<M> {
+    /// Wrap `db` with `prefix`, which should end with a separator such as `:`.
+    pub fn new<S>(db: Arc<M>, prefix: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            db,
+            prefix: prefix.into(),
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Inner database without the namespace.
+    pub fn inner(&self) -> &Arc<M> {
+        &self.db
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    /// Glob-style `pattern` inside the namespace.
+    fn pattern(&self, pattern: &str) -> String {
+        let mut ret = String::with_capacity(self.prefix.len() + pattern.len());
+        for c in self.prefix.chars() {
+            // Understood by both glob and Redis, `]` is literal outside of a class.
+            if matches!(c, '*' | '?' | '[') {
+                ret.extend(['[', c, ']']);
+            } else {
+                ret.push(c);
+            }
+        }
+        ret.push_str(pattern);
+        ret
+    }
+
+    fn strip(prefix: &str, key: String) -> String {
+        match key.strip_prefix(prefix) {
+            Some(x) => x.to_owned(),
+            None => key,
+        }
+    }
+}
+
+#[async_trait]
+impl<M: MemoryDB + ?Sized> MemoryDB for Namespaced<M> {
+    async fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.db.set(&self.key(key), value).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        self.db.get(&self.key(key)).await
+    }
+
+    async fn get_del(&self, key: &str) -> Result<Option<String>> {
+        self.db.get_del(&self.key(key)).await
+    }
+
+    async fn get_ex(&self, key: &str, ttl: &Duration) -> Result<Option<String>> {
+        self.db.get_ex(&self.key(key), ttl).await
+    }
+
+    async fn set_ex(&self, key: &str, value: &str, ttl: &Duration) -> Result<()> {
+        self.db.set_ex(&self.key(key), value, ttl).await
+    }
+
+    async fn set_bytes(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.db.set_bytes(&self.key(key), value).await
+    }
+
+    async fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.db.get_bytes(&self.key(key)).await
+    }
+
+    async fn get_del_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.db.get_del_bytes(&self.key(key)).await
+    }
+
+    async fn get_ex_bytes(&self, key: &str, ttl: &Duration) -> Result<Option<Vec<u8>>> {
+        self.db.get_ex_bytes(&self.key(key), ttl).await
+    }
+
+    async fn set_ex_bytes(&self, key: &str, value: &[u8], ttl: &Duration) -> Result<()> {
+        self.db.set_ex_bytes(&self.key(key), value, ttl).await
+    }
+
+    async fn set_nx(&self, key: &str, value: &str) -> Result<bool> {
+        self.db.set_nx(&self.key(key), value).await
+    }
+
+    async fn set_ex_nx(&self, key: &str, value: &str, ttl: &Duration) -> Result<bool> {
+        self.db.set_ex_nx(&self.key(key), value, ttl).await
+    }
+
+    async fn del(&self, key: &str) -> Result<bool> {
+        self.db.del(&self.key(key)).await
+    }
+
+    async fn del_if_eq(&self, key: &str, value: &str) -> Result<bool> {
+        self.db.del_if_eq(&self.key(key), value).await
+    }
+
+    async fn expire(&self, key: &str, ttl: i64) -> Result<bool> {
+        self.db.expire(&self.key(key), ttl).await
+    }
+
+    async fn expire_if_eq(&self, key: &str, value: &str, ttl: &Duration) -> Result<bool> {
+        self.db.expire_if_eq(&self.key(key), value, ttl).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.db.dels_match(&self.pattern("*")).await?;
+        Ok(())
+    }
+
+    async fn keys(&self, key: &str) -> Result<Vec<String>> {
+        Ok(self
+            .db
+            .keys(&self.pattern(key))
+            .await?
+            .into_iter()
+            .map(|x| Self::strip(&self.prefix, x))
+            .collect())
+    }
+
+    async fn scan(&self, pattern: &str, count: usize) -> Result<KeyStream> {
+        let prefix = self.prefix.clone();
+        Ok(self
+            .db
+            .scan(&self.pattern(pattern), count)
+            .await?
+            .map(move |x| x.map(|x| Self::strip(&prefix, x)))
+            .boxed())
+    }
+
+    async fn dels(&self, keys: &[String]) -> Result<u64> {
+        let keys: Vec<_> = keys.iter().map(|x| self.key(x)).collect();
+        self.db.dels(&keys).await
+    }
+
+    async fn dels_match(&self, pattern: &str) -> Result<u64> {
+        self.db.dels_match(&self.pattern(pattern)).await
+    }
+
+    async fn mget(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        let keys: Vec<_> = keys.iter().map(|x| self.key(x)).collect();
+        self.db.mget(&keys).await
+    }
+
+    async fn exec(&self, tx: &Transaction) -> Result<Vec<Reply>> {
+        self.db.exec(&tx.map_keys(|x| self.key(x))).await
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<i64>> {
+        self.db.ttl(&self.key(key)).await
+    }
+
+    async fn pttl(&self, key: &str) -> Result<Option<i64>> {
+        self.db.pttl(&self.key(key)).await
+    }
+
+    async fn incr_by(&self, key: &str, delta: i64, ttl: Option<&Duration>) -> Result<i64> {
+        self.db.incr_by(&self.key(key), delta, ttl).await
+    }
+
+    async fn decr_by(&self, key: &str, delta: i64, ttl: Option<&Duration>) -> Result<i64> {
+        self.db.decr_by(&self.key(key), delta, ttl).await
+    }
+
+    async fn hset(&self, key: &str, field: &str, value: &str) -> Result<bool> {
+        self.db.hset(&self.key(key), field, value).await
+    }
+
+    async fn hget(&self, key: &str, field: &str) -> Result<Option<String>> {
+        self.db.hget(&self.key(key), field).await
+    }
+
+    async fn hdel(&self, key: &str, field: &str) -> Result<bool> {
+        self.db.hdel(&self.key(key), field).await
+    }
+
+    async fn hgetall(&self, key: &str) -> Result<HashMap<String, String>> {
+        self.db.hgetall(&self.key(key)).await
+    }
+
+    async fn hincr(&self, key: &str, field: &str, delta: i64) -> Result<i64> {
+        self.db.hincr(&self.key(key), field, delta).await
+    }
+
+    async fn zadd(&self, key: &str, member: &str, score: f64) -> Result<bool> {
+        self.db.zadd(&self.key(key), member, score).await
+    }
+
+    async fn zrem(&self, key: &str, member: &str) -> Result<bool> {
+        self.db.zrem(&self.key(key), member).await
+    }
+
+    async fn zrange_by_score(&self, key: &str, min: f64, max: f64) -> Result<Vec<(String, f64)>> {
+        self.db.zrange_by_score(&self.key(key), min, max).await
+    }
+
+    async fn zrange_by_score_limit(
+        &self,
+        key: &str,
+        min: f64,
+        max: f64,
+        offset: usize,
+        count: usize,
+    ) -> Result<Vec<(String, f64)>> {
+        self.db
+            .zrange_by_score_limit(&self.key(key), min, max, offset, count)
+            .await
+    }
+
+    async fn zrem_range_by_score(&self, key: &str, min: f64, max: f64) -> Result<u64> {
+        self.db.zrem_range_by_score(&self.key(key), min, max).await
+    }
+
+    async fn zcard(&self, key: &str) -> Result<u64> {
+        self.db.zcard(&self.key(key)).await
+    }
+
+    async fn publish(&self, channel: &str, message: &str) -> Result<u64> {
+        self.db.publish(&self.key(channel), message).await
+    }
+
+    async fn subscribe(&self, pattern: &str) -> Result<MessageStream> {
+        let prefix = self.prefix.clone();
+        Ok(self
+            .db
+            .subscribe(&self.pattern(pattern))
+            .await?
+            .filter_map(move |x| {
+                future::ready(x.channel.strip_prefix(&prefix).map(|c| Message {
+                    channel: c.to_owned(),
+                    payload: x.payload.clone(),
+                }))
+            })
+            .boxed())
+    }
+
+    async fn watch(&self, pattern: &str) -> Result<EventStream> {
+        let prefix = self.prefix.clone();
+        Ok(self
+            .db
+            .watch(&self.pattern(pattern))
+            .await?
+            .filter_map(move |x| {
+                future::ready(x.key.strip_prefix(&prefix).map(|k| Event {
+                    key: k.to_owned(),
+                    kind: x.kind,
+                }))
+            })
+            .boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memorydb::{
+        clock::MockClock, default::DefaultBackend, interface::EventKind, testing,
+    };
+
+    #[tokio::test]
+    async fn test_conformance() {
+        let clock = MockClock::new();
+        let db = Arc::new(
+            DefaultBackend::builder()
+                .clock(clock.clone())
+                .build()
+                .unwrap(),
+        );
+        db.set("key", "value").await.unwrap();
+        testing::conformance(
+            &Namespaced::new(db.clone(), "ns*:"),
+            &testing::Time::Mock(clock),
+        )
+        .await;
+        assert_eq!(db.get("key").await.unwrap().unwrap(), "value");
+    }
+
+    #[tokio::test]
+    async fn test_namespaced() {
+        let db: Arc<dyn MemoryDB> = Arc::new(DefaultBackend::new(None));
+        let ns1 = Namespaced::new(db.clone(), "ns[1]:");
+        let ns2 = Namespaced::new(db.clone(), "ns2:");
+        let value = "value";
+
+        ns1.set("key1", value).await.unwrap();
+        ns1.transaction(&|tx| {
+            tx.hset("key2", "field", value);
+        })
+        .await
+        .unwrap();
+        ns2.set("key1", value).await.unwrap();
+        db.set("key1", value).await.unwrap();
+        assert_eq!(db.get("ns[1]:key1").await.unwrap().unwrap(), value);
+        assert_eq!(ns1.hget("key2", "field").await.unwrap().unwrap(), value);
+        assert_eq!(
+            ns2.mget(&["key1".to_owned(), "key2".to_owned()])
+                .await
+                .unwrap(),
+            [Some(value.to_owned()), None]
+        );
+
+        let mut keys = ns1.keys("key*").await.unwrap();
+        keys.sort();
+        assert_eq!(keys, ["key1", "key2"]);
+        let keys: Vec<_> = ns2
+            .scan("*", 10)
+            .await
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(keys, ["key1"]);
+
+        let mut s = ns1.subscribe("chan*").await.unwrap();
+        assert_eq!(ns2.publish("channel", value).await.unwrap(), 0);
+        assert_eq!(ns1.publish("channel", value).await.unwrap(), 1);
+        assert_eq!(
+            s.next().await.unwrap(),
+            Message {
+                channel: "channel".to_owned(),
+                payload: value.to_owned()
+            }
+        );
+
+        let mut events = ns2.watch("*").await.unwrap();
+        ns1.set("key1", value).await.unwrap();
+        ns2.set("key1", value).await.unwrap();
+        assert_eq!(
+            events.next().await.unwrap(),
+            Event {
+                key: "key1".to_owned(),
+                kind: EventKind::Set
+            }
+        );
+
+        ns1.flush().await.unwrap();
+        assert_eq!(ns1.keys("*").await.unwrap().len(), 0);
+        assert_eq!(ns2.get("key1").await.unwrap().unwrap(), value);
+        assert_eq!(db.get("key1").await.unwrap().unwrap(), value);
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use anyhow::anyhow;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    clock::Clock,
+    default::{Data, Fsync, Shards},
+};
+use crate::Result;
+
+/// Record in the append-only log.
+///
+/// Each record stores the full state of a key, so replaying the log is idempotent.
+#[derive(Serialize, Deserialize)]
+pub(super) enum Record<K, D> {
+    Set(K, D),
+    Del(K),
+    Flush,
+}
+
+/// Serialize binary-safe strings as JSON strings if possible.
+pub(super) mod bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Bytes {
+        String(String),
+        Bytes(Vec<u8>),
+    }
+
+    pub fn serialize<S: Serializer>(value: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(value) {
+            Ok(x) => serializer.serialize_str(x),
+            Err(_) => serializer.serialize_bytes(value),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        Ok(match Bytes::deserialize(deserializer)? {
+            Bytes::String(x) => x.into_bytes(),
+            Bytes::Bytes(x) => x,
+        })
+    }
+}
+
+/// Serialize sorted sets as maps from members to scores, infinite scores as strings.
+pub(super) mod zset {
+    use std::collections::HashMap;
+
+    use serde::{de::Error, ser::SerializeMap, Deserialize, Deserializer, Serializer};
+
+    use super::super::default::ZSet;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Score {
+        Float(f64),
+        String(String),
+    }
+
+    pub fn serialize<S: Serializer>(value: &ZSet, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(value.len()))?;
+        for (k, v) in value.iter() {
+            if v.is_finite() {
+                map.serialize_entry(k, &v)?;
+            } else {
+                map.serialize_entry(k, &v.to_string())?;
+            }
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ZSet, D::Error> {
+        let mut ret = ZSet::default();
+        for (k, v) in HashMap::<String, Score>::deserialize(deserializer)? {
+            let score = match v {
+                Score::Float(x) => x,
+                Score::String(x) => x.parse().map_err(D::Error::custom)?,
+            };
+            ret.insert(&k, score);
+        }
+        Ok(ret)
+    }
+}
+
+/// Number of records buffered for the log writer before writes block.
+const LOG_BUFFER: usize = 1024;
+
+enum Command {
+    Record(Vec<u8>),
+    /// Flush and sync all buffered records.
+    Sync(SyncSender<()>),
+    /// Flush all buffered records and truncate the log.
+    Truncate(SyncSender<()>),
+}
+
+/// Append-only log file, owned by the writer thread.
+struct Log {
+    file: BufWriter<File>,
+    fsync: Fsync,
+    synced: Instant,
+}
+
+impl Log {
+    /// Flush records to the OS and sync them according to the policy, or always if `force`.
+    fn flush(&mut self, force: bool) -> io::Result<()> {
+        self.file.flush()?;
+        let sync = force
+            || match self.fsync {
+                Fsync::Never => false,
+                Fsync::Always => true,
+                Fsync::Interval(x) => self.synced.elapsed() >= x,
+            };
+        if sync {
+            self.file.get_ref().sync_data()?;
+            self.synced = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Write records until all senders are dropped.
+    ///
+    /// Queued records are written as a batch before flushing, so a burst of writes is synced once.
+    /// The first error is kept in `error` and reported by the next write.
+    fn run(mut self, rx: Receiver<Command>, error: &Mutex<Option<io::Error>>) {
+        let keep = |x: io::Result<()>| {
+            if let Err(e) = x {
+                error.lock().get_or_insert(e);
+            }
+        };
+        while let Ok(cmd) = rx.recv() {
+            let mut next = Some(cmd);
+            while let Some(cmd) = next.take().or_else(|| rx.try_recv().ok()) {
+                match cmd {
+                    Command::Record(x) => keep(self.file.write_all(&x)),
+                    Command::Sync(tx) => {
+                        keep(self.flush(true));
+                        let _ = tx.send(());
+                    }
+                    Command::Truncate(tx) => {
+                        keep(self.file.flush());
+                        keep(self.file.get_ref().set_len(0));
+                        let _ = tx.send(());
+                    }
+                }
+            }
+            keep(self.flush(false));
+        }
+        keep(self.flush(true));
+    }
+}
+
+/// Handle of the log writer thread.
+struct Writer {
+    tx: SyncSender<Command>,
+    thread: JoinHandle<()>,
+    error: Arc<Mutex<Option<io::Error>>>,
+}
+
+impl Writer {
+    fn spawn(log: Log) -> Result<Self> {
+        let (tx, rx) = mpsc::sync_channel(LOG_BUFFER);
+        let error = Arc::new(Mutex::new(None));
+        let thread = thread::Builder::new().name("memorydb-aof".into()).spawn({
+            let error = error.clone();
+            move || log.run(rx, &error)
+        })?;
+        Ok(Self { tx, thread, error })
+    }
+
+    fn send(&self, cmd: Command) -> Result<()> {
+        if let Some(e) = self.error.lock().take() {
+            return Err(e.into());
+        }
+        self.tx
+            .send(cmd)
+            .map_err(|_| anyhow!("Append-only log writer is stopped"))
+    }
+
+    /// Send a command built from an acknowledgement sender and wait for it to finish.
+    fn wait(&self, cmd: fn(SyncSender<()>) -> Command) -> Result<()> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.send(cmd(tx))?;
+        rx.recv()
+            .map_err(|_| anyhow!("Append-only log writer is stopped"))?;
+        match self.error.lock().take() {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+pub(super) struct Persister {
+    data: Arc<Shards>,
+    path: PathBuf,
+    aof: Option<Writer>,
+}
+
+impl Persister {
+    /// Load the snapshot at `path` and replay the append-only log at `aof` into `shards` shards.
+    pub fn load(
+        path: PathBuf,
+        aof: Option<PathBuf>,
+        fsync: Fsync,
+        shards: usize,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self> {
+        let mut data: HashMap<String, Data> = match File::open(&path) {
+            Ok(x) => serde_json::from_reader(BufReader::new(x))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let aof = if let Some(aof) = aof {
+            if aof.exists() {
+                Self::replay(&aof, &mut data)?;
+            }
+            let file = OpenOptions::new().create(true).append(true).open(&aof)?;
+            Some(Writer::spawn(Log {
+                file: BufWriter::new(file),
+                fsync,
+                synced: Instant::now(),
+            })?)
+        } else {
+            None
+        };
+        let shards = Shards::new(shards, clock);
+        let now = shards.now();
+        for (k, v) in data {
+            if v.valid(now) {
+                shards.insert(k, v);
+            }
+        }
+
+        Ok(Self {
+            data: Arc::new(shards),
+            path,
+            aof,
+        })
+    }
+
+    fn replay(aof: &Path, data: &mut HashMap<String, Data>) -> Result<()> {
+        let mut lines = BufReader::new(File::open(aof)?).lines().peekable();
+        while let Some(line) = lines.next() {
+            let line = line?;
+            let record = match serde_json::from_str(&line) {
+                Ok(x) => x,
+                // The last record may be partially written.
+                Err(_) if lines.peek().is_none() => break,
+                Err(e) => return Err(e.into()),
+            };
+            match record {
+                Record::Set(k, v) => {
+                    data.insert(k, v);
+                }
+                Record::Del(k) => {
+                    data.remove(&k);
+                }
+                Record::Flush => data.clear(),
+            }
+        }
+        Ok(())
+    }
+
+    /// Loaded data, snapshots are taken from it.
+    pub fn data(&self) -> Arc<Shards> {
+        self.data.clone()
+    }
+
+    /// Append `record` to the log, no-op if the log is disabled.
+    ///
+    /// The record is only serialized here and written by the writer thread, which flushes it to
+    /// the OS and syncs it to disk according to the [`Fsync`] policy.
+    /// Block if the writer is [`LOG_BUFFER`] records behind.
+    pub fn append(&self, record: Record<&str, &Data>) -> Result<()> {
+        if let Some(aof) = &self.aof {
+            let mut line = serde_json::to_vec(&record)?;
+            line.push(b'\n');
+            aof.send(Command::Record(line))?;
+        }
+        Ok(())
+    }
+
+    /// Wait until all appended records are written and synced to disk.
+    pub fn sync(&self) -> Result<()> {
+        match &self.aof {
+            Some(aof) => aof.wait(Command::Sync),
+            None => Ok(()),
+        }
+    }
+
+    /// Write a snapshot and truncate the log.
+    ///
+    /// Writers are blocked until the snapshot is finished.
+    pub fn snapshot(&self) -> Result<()> {
+        let rlocks: Vec<_> = self.data.iter().map(|x| x.read()).collect();
+        let now = self.data.now();
+        let data: HashMap<_, _> = rlocks
+            .iter()
+            .flat_map(|x| x.iter())
+            .filter(|(_, v)| v.valid(now))
+            .collect();
+
+        let mut tmp = OsString::from(self.path.as_os_str());
+        tmp.push(".tmp");
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut file, &data)?;
+        file.into_inner()?.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+
+        // Records queued before the snapshot are written first, then discarded with the log.
+        if let Some(aof) = &self.aof {
+            aof.wait(Command::Truncate)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Persister {
+    fn drop(&mut self) {
+        let _ = self.snapshot();
+        if let Some(aof) = self.aof.take() {
+            drop(aof.tx);
+            let _ = aof.thread.join();
+        }
+    }
+}
@@ -0,0 +1,978 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use futures::{channel::mpsc, stream, StreamExt};
+use glob::Pattern;
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+use super::{
+    clock::{Clock, SystemClock},
+    default::{incr, utf8, NAN_SCORE, WRONG_TYPE},
+    interface::{KeyStream, MemoryDB, Message, MessageStream, Op, Reply, Transaction},
+};
+use crate::Result;
+
+const STRING: i64 = 0;
+const HASH: i64 = 1;
+const SORTED_SET: i64 = 2;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS memorydb (
+    key TEXT PRIMARY KEY NOT NULL,
+    type INTEGER NOT NULL,
+    value BLOB NOT NULL,
+    expire INTEGER
+) WITHOUT ROWID;
+CREATE INDEX IF NOT EXISTS memorydb_expire ON memorydb (expire) WHERE expire IS NOT NULL;
+CREATE TABLE IF NOT EXISTS memorydb_hash (
+    key TEXT NOT NULL,
+    field TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (key, field)
+) WITHOUT ROWID;
+CREATE TABLE IF NOT EXISTS memorydb_zset (
+    key TEXT NOT NULL,
+    member TEXT NOT NULL,
+    score REAL NOT NULL,
+    PRIMARY KEY (key, member)
+) WITHOUT ROWID;
+CREATE INDEX IF NOT EXISTS memorydb_zset_score ON memorydb_zset (key, score, member);
+";
+
+/// Condition of keys that are not expired, `?1` is the current time.
+const VALID: &str = "(expire IS NULL OR expire > ?1)";
+
+fn millis(ttl: &Duration) -> Result<i64> {
+    Ok(ttl.as_millis().try_into()?)
+}
+
+/// Type of the valid `key`.
+fn key_type(c: &Connection, now: i64, key: &str) -> Result<Option<i64>> {
+    Ok(c.query_row(
+        &format!("SELECT type FROM memorydb WHERE key = ?2 AND {VALID}"),
+        params![now, key],
+        |r| r.get(0),
+    )
+    .optional()?)
+}
+
+/// Check the valid `key` is of type `ty`, return `false` if it does not exist.
+fn is_type(c: &Connection, now: i64, key: &str, ty: i64) -> Result<bool> {
+    match key_type(c, now, key)? {
+        Some(x) if x == ty => Ok(true),
+        Some(_) => bail!(WRONG_TYPE),
+        None => Ok(false),
+    }
+}
+
+fn check_score(score: f64) -> Result<()> {
+    if score.is_nan() {
+        bail!(NAN_SCORE);
+    }
+    Ok(())
+}
+
+/// Remove hash fields and sorted set members of `key`.
+fn clear(c: &Connection, key: &str) -> Result<()> {
+    c.execute("DELETE FROM memorydb_hash WHERE key = ?1", [key])?;
+    c.execute("DELETE FROM memorydb_zset WHERE key = ?1", [key])?;
+    Ok(())
+}
+
+fn get_string(c: &Connection, now: i64, key: &str) -> Result<Option<Vec<u8>>> {
+    let ret: Option<(i64, Vec<u8>)> = c
+        .query_row(
+            &format!("SELECT type, value FROM memorydb WHERE key = ?2 AND {VALID}"),
+            params![now, key],
+            |r| Ok((r.get(0)?, r.get(1)?)),
+        )
+        .optional()?;
+    match ret {
+        Some((STRING, x)) => Ok(Some(x)),
+        Some(_) => bail!(WRONG_TYPE),
+        None => Ok(None),
+    }
+}
+
+/// Remove `key` if it is expired, so that writes only see valid keys.
+fn purge_key(c: &Connection, now: i64, key: &str) -> Result<()> {
+    if c.execute(
+        "DELETE FROM memorydb WHERE key = ?2 AND expire <= ?1",
+        params![now, key],
+    )? > 0
+    {
+        clear(c, key)?;
+    }
+    Ok(())
+}
+
+fn remove(c: &Connection, now: i64, key: &str) -> Result<bool> {
+    purge_key(c, now, key)?;
+    clear(c, key)?;
+    Ok(c.execute("DELETE FROM memorydb WHERE key = ?1", [key])? > 0)
+}
+
+fn put(c: &Connection, key: &str, value: &[u8], expire: Option<i64>) -> Result<()> {
+    clear(c, key)?;
+    c.execute(
+        "INSERT INTO memorydb (key, type, value, expire) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (key) DO UPDATE SET type = ?2, value = ?3, expire = ?4",
+        params![key, STRING, value, expire],
+    )?;
+    Ok(())
+}
+
+/// Insert `key` if it does not exist.
+fn put_nx(c: &Connection, now: i64, key: &str, value: &[u8], expire: Option<i64>) -> Result<bool> {
+    purge_key(c, now, key)?;
+    Ok(c.execute(
+        "INSERT INTO memorydb (key, type, value, expire) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (key) DO NOTHING",
+        params![key, STRING, value, expire],
+    )? > 0)
+}
+
+fn set_expire(c: &Connection, now: i64, key: &str, ttl: i64) -> Result<bool> {
+    Ok(c.execute(
+        &format!("UPDATE memorydb SET expire = ?3 WHERE key = ?2 AND {VALID}"),
+        params![now, key, now.saturating_add(ttl)],
+    )? > 0)
+}
+
+fn expire(c: &Connection, now: i64, key: &str, ttl: i64) -> Result<bool> {
+    if ttl <= 0 {
+        return remove(c, now, key);
+    }
+    set_expire(c, now, key, ttl.saturating_mul(1000))
+}
+
+/// `ttl` in milliseconds is only set when `key` is created.
+fn incr_by(c: &Connection, now: i64, key: &str, delta: i64, ttl: Option<i64>) -> Result<i64> {
+    purge_key(c, now, key)?;
+    if let Some(v) = get_string(c, now, key)? {
+        let ret = incr(&v, delta)?;
+        c.execute(
+            "UPDATE memorydb SET value = ?2 WHERE key = ?1",
+            params![key, ret.to_string().into_bytes()],
+        )?;
+        return Ok(ret);
+    }
+    put(
+        c,
+        key,
+        delta.to_string().as_bytes(),
+        ttl.map(|x| now.saturating_add(x)),
+    )?;
+    Ok(delta)
+}
+
+/// Create `key` of type `ty` if it does not exist.
+fn entry(c: &Connection, now: i64, key: &str, ty: i64) -> Result<()> {
+    purge_key(c, now, key)?;
+    if !is_type(c, now, key, ty)? {
+        c.execute(
+            "INSERT INTO memorydb (key, type, value) VALUES (?1, ?2, X'')",
+            params![key, ty],
+        )?;
+    }
+    Ok(())
+}
+
+fn hget(c: &Connection, key: &str, field: &str) -> Result<Option<String>> {
+    Ok(c.query_row(
+        "SELECT value FROM memorydb_hash WHERE key = ?1 AND field = ?2",
+        [key, field],
+        |r| r.get(0),
+    )
+    .optional()?)
+}
+
+fn hset(c: &Connection, now: i64, key: &str, field: &str, value: &str) -> Result<bool> {
+    entry(c, now, key, HASH)?;
+    let ret = hget(c, key, field)?.is_none();
+    c.execute(
+        "INSERT INTO memorydb_hash (key, field, value) VALUES (?1, ?2, ?3)
+        ON CONFLICT (key, field) DO UPDATE SET value = ?3",
+        [key, field, value],
+    )?;
+    Ok(ret)
+}
+
+fn hdel(c: &Connection, now: i64, key: &str, field: &str) -> Result<bool> {
+    purge_key(c, now, key)?;
+    if !is_type(c, now, key, HASH)? {
+        return Ok(false);
+    }
+    let ret = c.execute(
+        "DELETE FROM memorydb_hash WHERE key = ?1 AND field = ?2",
+        [key, field],
+    )? > 0;
+    c.execute(
+        "DELETE FROM memorydb WHERE key = ?1
+        AND NOT EXISTS (SELECT 1 FROM memorydb_hash WHERE key = ?1)",
+        [key],
+    )?;
+    Ok(ret)
+}
+
+fn hincr(c: &Connection, now: i64, key: &str, field: &str, delta: i64) -> Result<i64> {
+    entry(c, now, key, HASH)?;
+    let ret = match hget(c, key, field)? {
+        Some(x) => incr(x.as_bytes(), delta)?,
+        None => delta,
+    };
+    c.execute(
+        "INSERT INTO memorydb_hash (key, field, value) VALUES (?1, ?2, ?3)
+        ON CONFLICT (key, field) DO UPDATE SET value = ?3",
+        params![key, field, ret.to_string()],
+    )?;
+    Ok(ret)
+}
+
+/// Remove the sorted set `key` if it is empty.
+fn zclean(c: &Connection, key: &str) -> Result<()> {
+    c.execute(
+        "DELETE FROM memorydb WHERE key = ?1
+        AND NOT EXISTS (SELECT 1 FROM memorydb_zset WHERE key = ?1)",
+        [key],
+    )?;
+    Ok(())
+}
+
+/// Remove all expired keys.
+fn purge(c: &Connection, now: i64) -> Result<u64> {
+    for i in ["memorydb_hash", "memorydb_zset"] {
+        c.execute(
+            &format!("DELETE FROM {i} WHERE key IN (SELECT key FROM memorydb WHERE expire <= ?1)"),
+            [now],
+        )?;
+    }
+    Ok(c.execute("DELETE FROM memorydb WHERE expire <= ?1", [now])? as u64)
+}
+
+/// Run `f` in a transaction with the current time `now`, which is only committed if `f` succeeds.
+///
+/// The write lock is acquired immediately, so that other processes cannot interleave.
+fn transaction<T, F>(conn: &mut Connection, now: i64, f: F) -> Result<T>
+where
+    F: FnOnce(&Connection, i64) -> Result<T>,
+{
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let ret = f(&tx, now)?;
+    tx.commit()?;
+    Ok(ret)
+}
+
+type Subscriber = (Pattern, mpsc::UnboundedSender<Message>);
+
+/// [`MemoryDB`] stored in a SQLite database file.
+///
+/// Data survives restarts and can be shared by several processes on the same host.
+/// Pub/sub messages are only delivered inside the current process.
+#[derive(Clone)]
+pub struct SqliteBackend {
+    conn: Arc<Mutex<Connection>>,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SqliteBackend {
+    /// Open or create the database at `path` with default options.
+    ///
+    /// # Panics
+    /// Panics if it is not called inside a tokio runtime.
+    pub fn new<P>(path: P) -> Result<Self>
+    where
+        P: Into<PathBuf>,
+    {
+        Self::builder(path).build()
+    }
+
+    pub fn builder<P>(path: P) -> SqliteBackendBuilder
+    where
+        P: Into<PathBuf>,
+    {
+        SqliteBackendBuilder {
+            path: path.into(),
+            busy_timeout: Duration::from_secs(5),
+            purge_interval: Some(Duration::from_secs(60)),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    fn open(path: &Path, busy_timeout: Duration) -> Result<Connection> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(busy_timeout)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(conn)
+    }
+
+    /// Remove all expired keys and return the number of removed keys.
+    ///
+    /// Expired keys are invisible before they are removed.
+    pub async fn purge(&self) -> Result<u64> {
+        self.write(purge).await
+    }
+
+    /// Run `f` on the connection in a blocking thread with the current time.
+    async fn read<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection, i64) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let clock = self.clock.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock();
+            f(&conn, clock.now())
+        })
+        .await?
+    }
+
+    /// Run `f` in a blocking thread, see [`transaction`].
+    async fn write<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection, i64) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let clock = self.clock.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock();
+            transaction(&mut conn, clock.now(), f)
+        })
+        .await?
+    }
+}
+
+pub struct SqliteBackendBuilder {
+    path: PathBuf,
+    busy_timeout: Duration,
+    purge_interval: Option<Duration>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SqliteBackendBuilder {
+    /// How long to wait for the lock held by other processes. Default is 5 seconds.
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.busy_timeout = timeout;
+        self
+    }
+
+    /// Interval to remove expired keys in the background. Default is 60 seconds.
+    pub fn purge_interval(mut self, interval: Duration) -> Self {
+        self.purge_interval = Some(interval);
+        self
+    }
+
+    /// Do not remove expired keys in the background, use [`SqliteBackend::purge`] instead.
+    pub fn no_purge(mut self) -> Self {
+        self.purge_interval = None;
+        self
+    }
+
+    /// Time source used to expire keys. Default is [`SystemClock`].
+    ///
+    /// Use [`MockClock`](super::clock::MockClock) to control time in tests.
+    pub fn clock<C>(mut self, clock: C) -> Self
+    where
+        C: Clock + 'static,
+    {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// # Panics
+    /// Panics if it is not called inside a tokio runtime while the purge interval is set.
+    pub fn build(self) -> Result<SqliteBackend> {
+        let ret = SqliteBackend {
+            conn: Arc::new(Mutex::new(SqliteBackend::open(
+                &self.path,
+                self.busy_timeout,
+            )?)),
+            subscribers: Default::default(),
+            clock: self.clock,
+        };
+
+        if let Some(interval) = self.purge_interval {
+            let weak = Arc::downgrade(&ret.conn);
+            let clock = ret.clock.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(interval);
+                loop {
+                    interval.tick().await;
+                    let Some(conn) = weak.upgrade() else {
+                        break;
+                    };
+                    let clock = clock.clone();
+                    let _ = tokio::task::spawn_blocking(move || {
+                        let mut conn = conn.lock();
+                        transaction(&mut conn, clock.now(), purge)
+                    })
+                    .await;
+                }
+            });
+        }
+
+        Ok(ret)
+    }
+}
+
+#[async_trait]
+impl MemoryDB for SqliteBackend {
+    async fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.set_bytes(key, value.as_bytes()).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        self.get_bytes(key).await?.map(utf8).transpose()
+    }
+
+    async fn get_del(&self, key: &str) -> Result<Option<String>> {
+        self.get_del_bytes(key).await?.map(utf8).transpose()
+    }
+
+    async fn get_ex(&self, key: &str, ttl: &Duration) -> Result<Option<String>> {
+        self.get_ex_bytes(key, ttl).await?.map(utf8).transpose()
+    }
+
+    async fn set_ex(&self, key: &str, value: &str, ttl: &Duration) -> Result<()> {
+        self.set_ex_bytes(key, value.as_bytes(), ttl).await
+    }
+
+    async fn set_bytes(&self, key: &str, value: &[u8]) -> Result<()> {
+        let (key, value) = (key.to_owned(), value.to_owned());
+        self.write(move |c, _| put(c, &key, &value, None)).await
+    }
+
+    async fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let key = key.to_owned();
+        self.read(move |c, now| get_string(c, now, &key)).await
+    }
+
+    async fn get_del_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let key = key.to_owned();
+        self.write(move |c, now| {
+            let ret = get_string(c, now, &key)?;
+            if ret.is_some() {
+                remove(c, now, &key)?;
+            }
+            Ok(ret)
+        })
+        .await
+    }
+
+    async fn get_ex_bytes(&self, key: &str, ttl: &Duration) -> Result<Option<Vec<u8>>> {
+        let (key, ttl) = (key.to_owned(), millis(ttl)?);
+        self.write(move |c, now| {
+            let ret = get_string(c, now, &key)?;
+            if ret.is_some() {
+                set_expire(c, now, &key, ttl)?;
+            }
+            Ok(ret)
+        })
+        .await
+    }
+
+    async fn set_ex_bytes(&self, key: &str, value: &[u8], ttl: &Duration) -> Result<()> {
+        let (key, value, ttl) = (key.to_owned(), value.to_owned(), millis(ttl)?);
+        self.write(move |c, now| put(c, &key, &value, Some(now.saturating_add(ttl))))
+            .await
+    }
+
+    async fn set_nx(&self, key: &str, value: &str) -> Result<bool> {
+        let (key, value) = (key.to_owned(), value.to_owned());
+        self.write(move |c, now| put_nx(c, now, &key, value.as_bytes(), None))
+            .await
+    }
+
+    async fn set_ex_nx(&self, key: &str, value: &str, ttl: &Duration) -> Result<bool> {
+        let (key, value, ttl) = (key.to_owned(), value.to_owned(), millis(ttl)?);
+        self.write(move |c, now| {
+            put_nx(
+                c,
+                now,
+                &key,
+                value.as_bytes(),
+                Some(now.saturating_add(ttl)),
+            )
+        })
+        .await
+    }
+
+    async fn del(&self, key: &str) -> Result<bool> {
+        let key = key.to_owned();
+        self.write(move |c, now| remove(c, now, &key)).await
+    }
+
+    async fn del_if_eq(&self, key: &str, value: &str) -> Result<bool> {
+        let (key, value) = (key.to_owned(), value.to_owned());
+        self.write(move |c, now| {
+            if get_string(c, now, &key)?.is_some_and(|x| x == value.as_bytes()) {
+                return remove(c, now, &key);
+            }
+            Ok(false)
+        })
+        .await
+    }
+
+    async fn expire(&self, key: &str, ttl: i64) -> Result<bool> {
+        let key = key.to_owned();
+        self.write(move |c, now| expire(c, now, &key, ttl)).await
+    }
+
+    async fn expire_if_eq(&self, key: &str, value: &str, ttl: &Duration) -> Result<bool> {
+        let (key, value, ttl) = (key.to_owned(), value.to_owned(), millis(ttl)?);
+        self.write(move |c, now| {
+            if get_string(c, now, &key)?.is_some_and(|x| x == value.as_bytes()) {
+                return set_expire(c, now, &key, ttl);
+            }
+            Ok(false)
+        })
+        .await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.write(|c, _| {
+            c.execute_batch(
+                "DELETE FROM memorydb_hash; DELETE FROM memorydb_zset; DELETE FROM memorydb;",
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn keys(&self, key: &str) -> Result<Vec<String>> {
+        // Match in Rust, SQLite GLOB has different semantics from `glob::Pattern`.
+        let p = Pattern::new(key)?;
+        self.read(move |c, now| {
+            let mut stmt = c.prepare(&format!("SELECT key FROM memorydb WHERE {VALID}"))?;
+            let mut ret = Vec::new();
+            for k in stmt.query_map([now], |r| r.get::<_, String>(0))? {
+                let k = k?;
+                if p.matches(&k) {
+                    ret.push(k);
+                }
+            }
+            Ok(ret)
+        })
+        .await
+    }
+
+    async fn scan(&self, pattern: &str, count: usize) -> Result<KeyStream> {
+        let p = Arc::new(Pattern::new(pattern)?);
+        let count = count.max(1);
+        // Pages of `count` keys ordered by key, the last key of each page is the cursor.
+        let state = (self.clone(), Some(String::new()));
+        Ok(stream::unfold(state, move |(db, cursor)| {
+            let p = p.clone();
+            async move {
+                let cursor = cursor?;
+                let page = db
+                    .read(move |c, now| {
+                        let mut stmt = c.prepare(&format!(
+                            "SELECT key FROM memorydb WHERE key > ?2 AND {VALID} ORDER BY key LIMIT ?3"
+                        ))?;
+                        let keys = stmt
+                            .query_map(params![now, cursor, count], |r| r.get::<_, String>(0))?
+                            .collect::<rusqlite::Result<Vec<_>>>()?;
+                        Ok(keys)
+                    })
+                    .await;
+                match page {
+                    Ok(keys) => {
+                        let next = if keys.len() < count {
+                            None
+                        } else {
+                            keys.last().cloned()
+                        };
+                        let keys: Vec<_> =
+                            keys.into_iter().filter(|x| p.matches(x)).map(Ok).collect();
+                        Some((stream::iter(keys), (db, next)))
+                    }
+                    Err(e) => Some((stream::iter(vec![Err(e)]), (db, None))),
+                }
+            }
+        })
+        .flatten()
+        .boxed())
+    }
+
+    async fn dels(&self, keys: &[String]) -> Result<u64> {
+        let keys = keys.to_owned();
+        self.write(move |c, now| {
+            let mut sum = 0;
+            for i in &keys {
+                if remove(c, now, i)? {
+                    sum += 1;
+                }
+            }
+            Ok(sum)
+        })
+        .await
+    }
+
+    async fn mget(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        let keys = keys.to_owned();
+        self.read(move |c, now| {
+            keys.iter()
+                .map(|x| get_string(c, now, x)?.map(utf8).transpose())
+                .collect()
+        })
+        .await
+    }
+
+    async fn exec(&self, tx: &Transaction) -> Result<Vec<Reply>> {
+        let ops = tx.ops().to_owned();
+        let ret = self
+            .write(move |c, now| {
+                let mut ret = Vec::with_capacity(ops.len());
+                let mut err = None;
+                for i in &ops {
+                    let reply = match i {
+                        Op::Set(k, v, ttl) => ttl
+                            .as_ref()
+                            .map(millis)
+                            .transpose()
+                            .and_then(|ttl| put(c, k, v, ttl.map(|x| now.saturating_add(x))))
+                            .map(|_| Reply::Ok),
+                        Op::Del(k) => remove(c, now, k).map(Reply::Bool),
+                        Op::Expire(k, ttl) => expire(c, now, k, *ttl).map(Reply::Bool),
+                        Op::IncrBy(k, delta) => incr_by(c, now, k, *delta, None).map(Reply::Int),
+                        Op::HSet(k, f, v) => hset(c, now, k, f, v).map(Reply::Bool),
+                        Op::HDel(k, f) => hdel(c, now, k, f).map(Reply::Bool),
+                        Op::HIncr(k, f, delta) => hincr(c, now, k, f, *delta).map(Reply::Int),
+                    };
+                    match reply {
+                        Ok(x) => ret.push(x),
+                        Err(e) => {
+                            err.get_or_insert(e);
+                        }
+                    }
+                }
+                // Commit the successful commands, the same as other backends.
+                Ok((ret, err))
+            })
+            .await?;
+        match ret {
+            (_, Some(e)) => Err(e),
+            (x, None) => Ok(x),
+        }
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<i64>> {
+        // Round to the nearest second, the same as Redis.
+        Ok(self.pttl(key).await?.map(|x| (x + 500) / 1000))
+    }
+
+    async fn pttl(&self, key: &str) -> Result<Option<i64>> {
+        let key = key.to_owned();
+        self.read(move |c, now| {
+            let ret: Option<Option<i64>> = c
+                .query_row(
+                    &format!("SELECT expire FROM memorydb WHERE key = ?2 AND {VALID}"),
+                    params![now, key],
+                    |r| r.get(0),
+                )
+                .optional()?;
+            Ok(ret.flatten().map(|x| x.saturating_sub(now)))
+        })
+        .await
+    }
+
+    async fn incr_by(&self, key: &str, delta: i64, ttl: Option<&Duration>) -> Result<i64> {
+        let ttl = match ttl {
+            Some(x) if x.as_millis() > 0 => Some(millis(x)?),
+            _ => None,
+        };
+        let key = key.to_owned();
+        self.write(move |c, now| incr_by(c, now, &key, delta, ttl))
+            .await
+    }
+
+    async fn decr_by(&self, key: &str, delta: i64, ttl: Option<&Duration>) -> Result<i64> {
+        let delta = delta
+            .checked_neg()
+            .ok_or_else(|| anyhow!("Increment or decrement would overflow"))?;
+        self.incr_by(key, delta, ttl).await
+    }
+
+    async fn hset(&self, key: &str, field: &str, value: &str) -> Result<bool> {
+        let (key, field, value) = (key.to_owned(), field.to_owned(), value.to_owned());
+        self.write(move |c, now| hset(c, now, &key, &field, &value))
+            .await
+    }
+
+    async fn hget(&self, key: &str, field: &str) -> Result<Option<String>> {
+        let (key, field) = (key.to_owned(), field.to_owned());
+        self.read(move |c, now| {
+            if !is_type(c, now, &key, HASH)? {
+                return Ok(None);
+            }
+            hget(c, &key, &field)
+        })
+        .await
+    }
+
+    async fn hdel(&self, key: &str, field: &str) -> Result<bool> {
+        let (key, field) = (key.to_owned(), field.to_owned());
+        self.write(move |c, now| hdel(c, now, &key, &field)).await
+    }
+
+    async fn hgetall(&self, key: &str) -> Result<HashMap<String, String>> {
+        let key = key.to_owned();
+        self.read(move |c, now| {
+            if !is_type(c, now, &key, HASH)? {
+                return Ok(HashMap::new());
+            }
+            let mut stmt = c.prepare("SELECT field, value FROM memorydb_hash WHERE key = ?1")?;
+            let ret = stmt
+                .query_map([&key], |r| Ok((r.get(0)?, r.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(ret)
+        })
+        .await
+    }
+
+    async fn hincr(&self, key: &str, field: &str, delta: i64) -> Result<i64> {
+        let (key, field) = (key.to_owned(), field.to_owned());
+        self.write(move |c, now| hincr(c, now, &key, &field, delta))
+            .await
+    }
+
+    async fn zadd(&self, key: &str, member: &str, score: f64) -> Result<bool> {
+        check_score(score)?;
+        let (key, member) = (key.to_owned(), member.to_owned());
+        self.write(move |c, now| {
+            entry(c, now, &key, SORTED_SET)?;
+            let ret = c.execute(
+                "INSERT INTO memorydb_zset (key, member, score) VALUES (?1, ?2, ?3)
+                ON CONFLICT (key, member) DO NOTHING",
+                params![key, member, score],
+            )? > 0;
+            if !ret {
+                c.execute(
+                    "UPDATE memorydb_zset SET score = ?3 WHERE key = ?1 AND member = ?2",
+                    params![key, member, score],
+                )?;
+            }
+            Ok(ret)
+        })
+        .await
+    }
+
+    async fn zrem(&self, key: &str, member: &str) -> Result<bool> {
+        let (key, member) = (key.to_owned(), member.to_owned());
+        self.write(move |c, now| {
+            purge_key(c, now, &key)?;
+            if !is_type(c, now, &key, SORTED_SET)? {
+                return Ok(false);
+            }
+            let ret = c.execute(
+                "DELETE FROM memorydb_zset WHERE key = ?1 AND member = ?2",
+                [&key, &member],
+            )? > 0;
+            zclean(c, &key)?;
+            Ok(ret)
+        })
+        .await
+    }
+
+    async fn zrange_by_score(&self, key: &str, min: f64, max: f64) -> Result<Vec<(String, f64)>> {
+        self.zrange_by_score_limit(key, min, max, 0, usize::MAX)
+            .await
+    }
+
+    async fn zrange_by_score_limit(
+        &self,
+        key: &str,
+        min: f64,
+        max: f64,
+        offset: usize,
+        count: usize,
+    ) -> Result<Vec<(String, f64)>> {
+        check_score(min)?;
+        check_score(max)?;
+        let key = key.to_owned();
+        let offset = i64::try_from(offset)?;
+        // Negative limit means no limit.
+        let count = i64::try_from(count).unwrap_or(-1);
+        self.read(move |c, now| {
+            if !is_type(c, now, &key, SORTED_SET)? {
+                return Ok(Vec::new());
+            }
+            let mut stmt = c.prepare(
+                "SELECT member, score FROM memorydb_zset
+                WHERE key = ?1 AND score >= ?2 AND score <= ?3 ORDER BY score, member
+                LIMIT ?4 OFFSET ?5",
+            )?;
+            let ret = stmt
+                .query_map(params![key, min, max, count, offset], |r| {
+                    Ok((r.get(0)?, r.get(1)?))
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(ret)
+        })
+        .await
+    }
+
+    async fn zrem_range_by_score(&self, key: &str, min: f64, max: f64) -> Result<u64> {
+        check_score(min)?;
+        check_score(max)?;
+        let key = key.to_owned();
+        self.write(move |c, now| {
+            purge_key(c, now, &key)?;
+            if !is_type(c, now, &key, SORTED_SET)? {
+                return Ok(0);
+            }
+            let ret = c.execute(
+                "DELETE FROM memorydb_zset WHERE key = ?1 AND score >= ?2 AND score <= ?3",
+                params![key, min, max],
+            )?;
+            zclean(c, &key)?;
+            Ok(ret as u64)
+        })
+        .await
+    }
+
+    async fn zcard(&self, key: &str) -> Result<u64> {
+        let key = key.to_owned();
+        self.read(move |c, now| {
+            if !is_type(c, now, &key, SORTED_SET)? {
+                return Ok(0);
+            }
+            let ret: i64 = c.query_row(
+                "SELECT COUNT(*) FROM memorydb_zset WHERE key = ?1",
+                [&key],
+                |r| r.get(0),
+            )?;
+            Ok(ret as u64)
+        })
+        .await
+    }
+
+    async fn publish(&self, channel: &str, message: &str) -> Result<u64> {
+        let mut sum = 0;
+        self.subscribers.lock().retain(|(p, tx)| {
+            if !p.matches(channel) {
+                return !tx.is_closed();
+            }
+            let ret = tx.unbounded_send(Message {
+                channel: channel.to_owned(),
+                payload: message.to_owned(),
+            });
+            if ret.is_ok() {
+                sum += 1;
+            }
+            ret.is_ok()
+        });
+        Ok(sum)
+    }
+
+    async fn subscribe(&self, pattern: &str) -> Result<MessageStream> {
+        let p = Pattern::new(pattern)?;
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().push((p, tx));
+        Ok(rx.boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::memorydb::{clock::MockClock, testing};
+
+    /// Database file removed on drop.
+    struct TempDB(PathBuf);
+
+    impl TempDB {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "actix_cloud_{}_{}.sqlite",
+                name,
+                std::process::id()
+            ));
+            let ret = Self(path);
+            ret.remove();
+            ret
+        }
+
+        fn remove(&self) {
+            for i in ["", "-wal", "-shm"] {
+                let _ = fs::remove_file(format!("{}{}", self.0.display(), i));
+            }
+        }
+    }
+
+    impl Drop for TempDB {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_conformance() {
+        let path = TempDB::new("conformance");
+        let clock = MockClock::new();
+        let db = SqliteBackend::builder(&path.0)
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        testing::conformance(&db, &testing::Time::Mock(clock)).await;
+    }
+
+    #[tokio::test]
+    async fn test_shared() {
+        let path = TempDB::new("shared");
+        let clock = MockClock::new();
+        let build = || {
+            SqliteBackend::builder(&path.0)
+                .no_purge()
+                .clock(clock.clone())
+                .build()
+                .unwrap()
+        };
+        let db1 = build();
+        let db2 = build();
+
+        db1.set("key1", "value").await.unwrap();
+        db1.hset("key2", "field", "value").await.unwrap();
+        db1.set_ex("key3", "value", &Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert_eq!(db2.get("key1").await.unwrap().unwrap(), "value");
+        assert!(db2.get("key2").await.is_err());
+        assert_eq!(db2.incr_by("key4", 1, None).await.unwrap(), 1);
+        assert_eq!(db1.incr_by("key4", 1, None).await.unwrap(), 2);
+
+        clock.advance(Duration::from_millis(200));
+        let mut keys = db2.keys("key[!4]").await.unwrap();
+        keys.sort();
+        assert_eq!(keys, ["key1", "key2"]);
+        assert_eq!(db1.purge().await.unwrap(), 1);
+        assert_eq!(db1.purge().await.unwrap(), 0);
+        assert_eq!(
+            db2.dels(&["key1".to_owned(), "key2".to_owned(), "key3".to_owned()])
+                .await
+                .unwrap(),
+            2
+        );
+        drop(db1);
+
+        // Data survives reopening.
+        let db1 = SqliteBackend::new(&path.0).unwrap();
+        assert_eq!(db1.get("key4").await.unwrap().unwrap(), "2");
+        assert_eq!(db1.keys("*").await.unwrap(), ["key4"]);
+    }
+}
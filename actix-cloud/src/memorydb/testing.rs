@@ -0,0 +1,725 @@
+//! Conformance test suite for [`MemoryDB`] backends.
+//!
+//! Every function panics when the backend does not behave like the built-in backends.
+//! Keys and channels are prefixed with `_actix_cloud_`.
+//!
+//! Backends built with a [`MockClock`] run without waiting for keys to expire.
+//!
+//! ```
+//! use actix_cloud::memorydb::{clock::MockClock, default::DefaultBackend, testing};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let clock = MockClock::new();
+//! let db = DefaultBackend::builder().clock(clock.clone()).build().unwrap();
+//! testing::conformance(&db, &testing::Time::Mock(clock)).await;
+//! # }
+//! ```
+use std::{collections::HashMap, time::Duration};
+
+use futures::{Stream, StreamExt};
+use tokio::{
+    task::yield_now,
+    time::{sleep, timeout},
+};
+
+use super::{
+    clock::MockClock,
+    interface::{Event, EventKind, MemoryDB, Reply, Transaction},
+};
+
+/// Time source of the backend under test, used to wait for keys to expire.
+#[derive(Debug, Clone, Default)]
+pub enum Time {
+    /// The backend uses the system clock, sleep in real time.
+    #[default]
+    System,
+    /// The backend uses the mock clock, advance it instead of sleeping.
+    Mock(MockClock),
+}
+
+impl Time {
+    /// Let `duration` pass for the backend.
+    pub async fn advance(&self, duration: Duration) {
+        match self {
+            Self::System => sleep(duration).await,
+            Self::Mock(x) => x.advance(duration),
+        }
+    }
+}
+
+/// Run the whole suite.
+///
+/// The database is flushed at the end, use a dedicated database.
+/// [`watch`] is not included since it is optional.
+pub async fn conformance<M: MemoryDB + ?Sized>(r: &M, time: &Time) {
+    normal(r).await;
+    bytes(r, time).await;
+    ex(r, time).await;
+    ex_ms(r, time).await;
+    expire(r, time).await;
+    batch(r).await;
+    transaction(r).await;
+    scan(r).await;
+    cond(r, time).await;
+    incr(r, time).await;
+    hash(r, time).await;
+    zset(r).await;
+    zset_limit(r).await;
+    pubsub(r).await;
+    flush(r).await;
+}
+
+/// `set`, `get` and `del`.
+pub async fn normal<M: MemoryDB + ?Sized>(r: &M) {
+    let key = "_actix_cloud_key1";
+    let value1 = "value1";
+    let value2 = "value2";
+
+    let _ = r.del(key).await;
+
+    assert_eq!(r.get(key).await.unwrap(), None);
+
+    r.set(key, value1).await.unwrap();
+    assert_eq!(r.get(key).await.unwrap().unwrap(), value1);
+    r.set(key, value2).await.unwrap();
+    assert_eq!(r.get(key).await.unwrap().unwrap(), value2);
+
+    assert!(r.del(key).await.unwrap());
+    assert!(!r.del(key).await.unwrap());
+    assert_eq!(r.get(key).await.unwrap(), None);
+    assert_eq!(r.get_del(key).await.unwrap(), None);
+    assert_eq!(r.get_ex(key, &Duration::from_secs(1)).await.unwrap(), None);
+    assert_eq!(r.ttl(key).await.unwrap(), None);
+    assert_eq!(r.pttl(key).await.unwrap(), None);
+}
+
+/// Binary values and their interaction with string values.
+pub async fn bytes<M: MemoryDB + ?Sized>(r: &M, time: &Time) {
+    let key = "_actix_cloud_bytes1";
+    let value = [0u8, 159, 146, 150, 255];
+
+    let _ = r.del(key).await;
+
+    r.set_bytes(key, &value).await.unwrap();
+    assert_eq!(r.get_bytes(key).await.unwrap().unwrap(), value);
+    assert!(r.get(key).await.is_err());
+    assert_eq!(
+        r.get_ex_bytes(key, &Duration::from_secs(10))
+            .await
+            .unwrap()
+            .unwrap(),
+        value
+    );
+    assert_eq!(r.ttl(key).await.unwrap(), Some(10));
+    assert_eq!(r.get_del_bytes(key).await.unwrap().unwrap(), value);
+    assert_eq!(r.get_bytes(key).await.unwrap(), None);
+
+    r.set_ex_bytes(key, &value, &Duration::from_millis(200))
+        .await
+        .unwrap();
+    assert_eq!(r.get_bytes(key).await.unwrap().unwrap(), value);
+    time.advance(Duration::from_millis(300)).await;
+    assert_eq!(r.get_bytes(key).await.unwrap(), None);
+
+    r.set(key, "value").await.unwrap();
+    assert_eq!(r.get_bytes(key).await.unwrap().unwrap(), b"value");
+    assert!(r.del(key).await.unwrap());
+}
+
+/// TTLs in seconds: `set_ex`, `get_ex`, `get_del` and `ttl`.
+pub async fn ex<M: MemoryDB + ?Sized>(r: &M, time: &Time) {
+    let key = "_actix_cloud_key2";
+    let value = "value";
+
+    let _ = r.del(key).await;
+
+    r.set(key, value).await.unwrap();
+    assert_eq!(r.ttl(key).await.unwrap(), None);
+    assert_eq!(r.get_del(key).await.unwrap().unwrap(), value);
+    assert_eq!(r.get(key).await.unwrap(), None);
+
+    r.set_ex(key, value, &Duration::from_secs(2)).await.unwrap();
+    assert_eq!(r.get(key).await.unwrap().unwrap(), value);
+    assert_eq!(r.ttl(key).await.unwrap(), Some(2));
+    time.advance(Duration::from_secs(1)).await;
+    assert_eq!(r.ttl(key).await.unwrap(), Some(1));
+    assert_eq!(
+        r.get_ex(key, &Duration::from_secs(2))
+            .await
+            .unwrap()
+            .unwrap(),
+        value
+    );
+    assert_eq!(r.ttl(key).await.unwrap(), Some(2));
+    time.advance(Duration::from_secs(1)).await;
+    assert_eq!(r.ttl(key).await.unwrap(), Some(1));
+    assert_eq!(r.get(key).await.unwrap().unwrap(), value);
+    time.advance(Duration::from_secs(2)).await;
+    assert_eq!(r.ttl(key).await.unwrap(), None);
+    assert_eq!(r.get(key).await.unwrap(), None);
+}
+
+/// Millisecond precise TTLs: `pttl` and TTL of conditional writes and counters.
+pub async fn ex_ms<M: MemoryDB + ?Sized>(r: &M, time: &Time) {
+    let key = "_actix_cloud_key4";
+    let value = "value";
+
+    let _ = r.del(key).await;
+
+    r.set(key, value).await.unwrap();
+    assert_eq!(r.pttl(key).await.unwrap(), None);
+
+    r.set_ex(key, value, &Duration::from_millis(600))
+        .await
+        .unwrap();
+    let ttl = r.pttl(key).await.unwrap().unwrap();
+    assert!(ttl > 400 && ttl <= 600);
+    assert_eq!(r.ttl(key).await.unwrap(), Some(1));
+    time.advance(Duration::from_millis(300)).await;
+    assert_eq!(r.ttl(key).await.unwrap(), Some(0));
+    assert_eq!(
+        r.get_ex(key, &Duration::from_millis(1500))
+            .await
+            .unwrap()
+            .unwrap(),
+        value
+    );
+    let ttl = r.pttl(key).await.unwrap().unwrap();
+    assert!(ttl > 1300 && ttl <= 1500);
+    assert!(r
+        .expire_if_eq(key, value, &Duration::from_millis(200))
+        .await
+        .unwrap());
+    time.advance(Duration::from_millis(300)).await;
+    assert_eq!(r.get(key).await.unwrap(), None);
+    assert_eq!(r.pttl(key).await.unwrap(), None);
+
+    assert!(r
+        .set_ex_nx(key, value, &Duration::from_millis(200))
+        .await
+        .unwrap());
+    assert!(!r.set_nx(key, value).await.unwrap());
+    time.advance(Duration::from_millis(300)).await;
+    assert_eq!(
+        r.incr_by(key, 1, Some(&Duration::from_millis(200)))
+            .await
+            .unwrap(),
+        1
+    );
+    time.advance(Duration::from_millis(300)).await;
+    assert_eq!(r.get(key).await.unwrap(), None);
+}
+
+/// `expire`, including zero and negative TTLs which delete the key.
+pub async fn expire<M: MemoryDB + ?Sized>(r: &M, time: &Time) {
+    let key = "_actix_cloud_key3";
+    let value = "value";
+
+    let _ = r.del(key).await;
+
+    r.set(key, value).await.unwrap();
+    assert_eq!(r.get(key).await.unwrap().unwrap(), value);
+    assert!(r.expire(key, 1).await.unwrap());
+    time.advance(Duration::from_secs(2)).await;
+    assert_eq!(r.get(key).await.unwrap(), None);
+    assert!(!r.expire(key, 1).await.unwrap());
+
+    r.set_ex(key, value, &Duration::from_secs(1)).await.unwrap();
+    assert!(r.expire(key, 3).await.unwrap());
+    time.advance(Duration::from_secs(2)).await;
+    assert_eq!(r.get(key).await.unwrap().unwrap(), value);
+    assert!(r.expire(key, -1).await.unwrap());
+    assert_eq!(r.get(key).await.unwrap(), None);
+    assert!(!r.expire(key, 0).await.unwrap());
+}
+
+/// `keys`, `dels`, `mget`, `mset` and `mset_ex`.
+pub async fn batch<M: MemoryDB + ?Sized>(r: &M) {
+    let key1 = "_actix_cloud_bkey1";
+    let key2 = "_actix_cloud_bkey2";
+    let value = "value";
+
+    let _ = r.del(key1).await;
+    let _ = r.del(key2).await;
+
+    r.set(key1, value).await.unwrap();
+    r.set(key2, value).await.unwrap();
+    let ret = r.keys("_actix_cl?ud_bkey*").await.unwrap();
+    assert_eq!(ret.len(), 2);
+    assert!((ret[0] == key1 && ret[1] == key2) || (ret[1] == key1 && ret[0] == key2));
+    assert_eq!(
+        r.dels(&[key1.to_owned(), key2.to_owned()]).await.unwrap(),
+        2
+    );
+    assert_eq!(r.keys("_actix_cl?ud_bkey*").await.unwrap().len(), 0);
+
+    r.mset(&[
+        (key1.to_owned(), value.to_owned()),
+        (key2.to_owned(), value.to_owned()),
+    ])
+    .await
+    .unwrap();
+    assert_eq!(
+        r.mget(&[
+            key1.to_owned(),
+            "_actix_cloud_bkey3".to_owned(),
+            key2.to_owned()
+        ])
+        .await
+        .unwrap(),
+        [Some(value.to_owned()), None, Some(value.to_owned())]
+    );
+    r.mset_ex(
+        &[(key1.to_owned(), value.to_owned())],
+        &Duration::from_secs(10),
+    )
+    .await
+    .unwrap();
+    assert_eq!(r.ttl(key1).await.unwrap(), Some(10));
+    assert_eq!(r.ttl(key2).await.unwrap(), None);
+    assert!(r.mget(&[]).await.unwrap().is_empty());
+    r.dels(&[key1.to_owned(), key2.to_owned()]).await.unwrap();
+}
+
+/// `exec` and `transaction`.
+pub async fn transaction<M: MemoryDB + ?Sized>(r: &M) {
+    let key1 = "_actix_cloud_tkey1";
+    let key2 = "_actix_cloud_tkey2";
+    let key3 = "_actix_cloud_tkey3";
+    let value = "value";
+
+    let _ = r
+        .dels(&[key1.to_owned(), key2.to_owned(), key3.to_owned()])
+        .await;
+
+    let ret = r
+        .transaction(&|tx| {
+            tx.set(key1, value)
+                .expire(key1, 10)
+                .incr_by(key2, 2)
+                .incr_by(key2, 3)
+                .hset(key3, "f1", value)
+                .hincr(key3, "f2", 1)
+                .hdel(key3, "f1")
+                .del(key2)
+                .del(key2);
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        ret,
+        [
+            Reply::Ok,
+            Reply::Bool(true),
+            Reply::Int(2),
+            Reply::Int(5),
+            Reply::Bool(true),
+            Reply::Int(1),
+            Reply::Bool(true),
+            Reply::Bool(true),
+            Reply::Bool(false)
+        ]
+    );
+    assert_eq!(r.get(key1).await.unwrap().unwrap(), value);
+    assert_eq!(r.ttl(key1).await.unwrap(), Some(10));
+    assert_eq!(r.get(key2).await.unwrap(), None);
+    assert_eq!(
+        r.hgetall(key3).await.unwrap(),
+        HashMap::from([("f2".to_owned(), "1".to_owned())])
+    );
+    assert!(r.exec(&Transaction::new()).await.unwrap().is_empty());
+
+    // Failed command does not roll back the others.
+    let mut tx = Transaction::new();
+    tx.incr_by(key1, 1).set(key2, value);
+    assert!(r.exec(&tx).await.is_err());
+    assert_eq!(r.get(key2).await.unwrap().unwrap(), value);
+
+    r.dels(&[key1.to_owned(), key2.to_owned(), key3.to_owned()])
+        .await
+        .unwrap();
+}
+
+/// `exec` on a full backend, where writes of new keys fail instead of evicting.
+///
+/// `r` must be empty and hold at most one key.
+/// Not included in [`conformance`] since it needs a capacity-limited backend.
+pub async fn transaction_full<M: MemoryDB + ?Sized>(r: &M) {
+    let key1 = "_actix_cloud_tkey1";
+    let key2 = "_actix_cloud_tkey2";
+    let value = "value";
+
+    r.set(key1, value).await.unwrap();
+    assert!(r.set(key2, value).await.is_err());
+
+    // The rejected command is skipped, the others still run.
+    let mut tx = Transaction::new();
+    tx.set(key2, value).set(key1, "value2").expire(key1, 10);
+    assert!(r.exec(&tx).await.is_err());
+    assert_eq!(r.get(key1).await.unwrap().unwrap(), "value2");
+    assert_eq!(r.ttl(key1).await.unwrap(), Some(10));
+    assert_eq!(r.get(key2).await.unwrap(), None);
+
+    r.del(key1).await.unwrap();
+}
+
+/// `scan` and `dels_match`.
+pub async fn scan<M: MemoryDB + ?Sized>(r: &M) {
+    let pattern = "_actix_cl?ud_skey*";
+    let value = "value";
+
+    let _ = r.dels_match(pattern).await;
+
+    for i in 0..250 {
+        r.set(&format!("_actix_cloud_skey{i}"), value)
+            .await
+            .unwrap();
+    }
+    r.set("_actix_cloud_other", value).await.unwrap();
+    let mut ret: Vec<_> = r
+        .scan(pattern, 10)
+        .await
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    ret.sort();
+    ret.dedup();
+    assert_eq!(ret.len(), 250);
+    assert!(ret.iter().all(|x| x.starts_with("_actix_cloud_skey")));
+
+    assert_eq!(r.dels_match(pattern).await.unwrap(), 250);
+    assert_eq!(r.scan(pattern, 10).await.unwrap().count().await, 0);
+    assert!(r.del("_actix_cloud_other").await.unwrap());
+}
+
+/// Conditional writes: `set_nx`, `set_ex_nx`, `del_if_eq` and `expire_if_eq`.
+pub async fn cond<M: MemoryDB + ?Sized>(r: &M, time: &Time) {
+    let key = "_actix_cloud_nkey1";
+    let value1 = "value1";
+    let value2 = "value2";
+
+    let _ = r.del(key).await;
+
+    assert!(r.set_nx(key, value1).await.unwrap());
+    assert!(!r.set_nx(key, value2).await.unwrap());
+    assert!(!r
+        .set_ex_nx(key, value2, &Duration::from_secs(1))
+        .await
+        .unwrap());
+    assert_eq!(r.get(key).await.unwrap().unwrap(), value1);
+    assert_eq!(r.ttl(key).await.unwrap(), None);
+
+    assert!(!r
+        .expire_if_eq(key, value2, &Duration::from_secs(1))
+        .await
+        .unwrap());
+    assert_eq!(r.ttl(key).await.unwrap(), None);
+    assert!(r
+        .expire_if_eq(key, value1, &Duration::from_secs(2))
+        .await
+        .unwrap());
+    assert_eq!(r.ttl(key).await.unwrap(), Some(2));
+
+    assert!(!r.del_if_eq(key, value2).await.unwrap());
+    assert!(r.del_if_eq(key, value1).await.unwrap());
+    assert!(!r.del_if_eq(key, value1).await.unwrap());
+    assert!(!r
+        .expire_if_eq(key, value1, &Duration::from_secs(1))
+        .await
+        .unwrap());
+
+    assert!(r
+        .set_ex_nx(key, value2, &Duration::from_secs(1))
+        .await
+        .unwrap());
+    assert_eq!(r.ttl(key).await.unwrap(), Some(1));
+    time.advance(Duration::from_secs(2)).await;
+    assert!(r.set_nx(key, value1).await.unwrap());
+    assert_eq!(r.get(key).await.unwrap().unwrap(), value1);
+
+    assert!(r.del(key).await.unwrap());
+}
+
+/// Counters: `incr_by` and `decr_by`.
+pub async fn incr<M: MemoryDB + ?Sized>(r: &M, time: &Time) {
+    let key = "_actix_cloud_ikey1";
+
+    let _ = r.del(key).await;
+
+    assert_eq!(r.incr_by(key, 2, None).await.unwrap(), 2);
+    assert_eq!(r.incr_by(key, 3, None).await.unwrap(), 5);
+    assert_eq!(r.decr_by(key, 6, None).await.unwrap(), -1);
+    assert_eq!(r.get(key).await.unwrap().unwrap(), "-1");
+    assert_eq!(r.ttl(key).await.unwrap(), None);
+
+    r.set(key, "value").await.unwrap();
+    assert!(r.incr_by(key, 1, None).await.is_err());
+    r.set(key, &i64::MAX.to_string()).await.unwrap();
+    assert!(r.incr_by(key, 1, None).await.is_err());
+    assert!(r.del(key).await.unwrap());
+
+    let ttl = Duration::from_secs(2);
+    assert_eq!(r.decr_by(key, 1, Some(&ttl)).await.unwrap(), -1);
+    assert_eq!(r.ttl(key).await.unwrap(), Some(2));
+    time.advance(Duration::from_secs(1)).await;
+    assert_eq!(
+        r.incr_by(key, 2, Some(&Duration::from_secs(10)))
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(r.ttl(key).await.unwrap(), Some(1));
+    time.advance(Duration::from_secs(2)).await;
+    assert_eq!(r.get(key).await.unwrap(), None);
+    assert_eq!(r.incr_by(key, 1, None).await.unwrap(), 1);
+    assert_eq!(r.ttl(key).await.unwrap(), None);
+
+    assert!(r.del(key).await.unwrap());
+}
+
+/// Hash operations and type errors between strings and hashes.
+pub async fn hash<M: MemoryDB + ?Sized>(r: &M, time: &Time) {
+    let key = "_actix_cloud_hkey1";
+
+    let _ = r.del(key).await;
+
+    assert_eq!(r.hget(key, "f1").await.unwrap(), None);
+    assert!(r.hgetall(key).await.unwrap().is_empty());
+    assert!(r.hset(key, "f1", "v1").await.unwrap());
+    assert!(!r.hset(key, "f1", "v2").await.unwrap());
+    assert_eq!(r.hget(key, "f1").await.unwrap().unwrap(), "v2");
+    assert_eq!(r.hincr(key, "f2", 2).await.unwrap(), 2);
+    assert_eq!(r.hincr(key, "f2", -3).await.unwrap(), -1);
+    assert!(r.hincr(key, "f1", 1).await.is_err());
+    assert_eq!(
+        r.hgetall(key).await.unwrap(),
+        HashMap::from([
+            ("f1".to_owned(), "v2".to_owned()),
+            ("f2".to_owned(), "-1".to_owned())
+        ])
+    );
+    assert!(r.get(key).await.is_err());
+    assert!(r.incr_by(key, 1, None).await.is_err());
+
+    assert!(r.hdel(key, "f1").await.unwrap());
+    assert!(!r.hdel(key, "f1").await.unwrap());
+    assert!(r.hdel(key, "f2").await.unwrap());
+    assert_eq!(r.keys(key).await.unwrap().len(), 0);
+
+    assert_eq!(r.hincr(key, "f1", 1).await.unwrap(), 1);
+    assert!(r.expire(key, 1).await.unwrap());
+    assert_eq!(r.ttl(key).await.unwrap(), Some(1));
+    time.advance(Duration::from_secs(2)).await;
+    assert_eq!(r.hget(key, "f1").await.unwrap(), None);
+    assert!(r.hgetall(key).await.unwrap().is_empty());
+
+    r.set(key, "value").await.unwrap();
+    assert!(r.hset(key, "f1", "v1").await.is_err());
+    assert!(r.hget(key, "f1").await.is_err());
+    assert!(r.del(key).await.unwrap());
+}
+
+/// Sorted set operations, ordering and type errors.
+pub async fn zset<M: MemoryDB + ?Sized>(r: &M) {
+    let key = "_actix_cloud_zkey1";
+
+    let _ = r.del(key).await;
+
+    assert_eq!(r.zcard(key).await.unwrap(), 0);
+    assert!(r
+        .zrange_by_score(key, f64::NEG_INFINITY, f64::INFINITY)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(r.zadd(key, "m1", 2.0).await.unwrap());
+    assert!(!r.zadd(key, "m1", 1.0).await.unwrap());
+    assert!(r.zadd(key, "m3", 2.0).await.unwrap());
+    assert!(r.zadd(key, "m2", 2.0).await.unwrap());
+    assert!(r.zadd(key, "m4", f64::INFINITY).await.unwrap());
+    assert!(r.zadd(key, "m5", -1.5).await.unwrap());
+    assert!(r.zadd(key, "m1", f64::NAN).await.is_err());
+    assert_eq!(r.zcard(key).await.unwrap(), 5);
+    assert_eq!(
+        r.zrange_by_score(key, f64::NEG_INFINITY, f64::INFINITY)
+            .await
+            .unwrap(),
+        [
+            ("m5".to_owned(), -1.5),
+            ("m1".to_owned(), 1.0),
+            ("m2".to_owned(), 2.0),
+            ("m3".to_owned(), 2.0),
+            ("m4".to_owned(), f64::INFINITY)
+        ]
+    );
+    assert_eq!(
+        r.zrange_by_score(key, 1.0, 2.0).await.unwrap(),
+        [
+            ("m1".to_owned(), 1.0),
+            ("m2".to_owned(), 2.0),
+            ("m3".to_owned(), 2.0)
+        ]
+    );
+    assert!(r.zrange_by_score(key, 3.0, 1.0).await.unwrap().is_empty());
+    assert!(r.get(key).await.is_err());
+    assert!(r.hset(key, "f1", "v1").await.is_err());
+
+    assert!(r.zrem(key, "m2").await.unwrap());
+    assert!(!r.zrem(key, "m2").await.unwrap());
+    assert_eq!(r.zrem_range_by_score(key, 0.0, 2.0).await.unwrap(), 2);
+    assert_eq!(r.zcard(key).await.unwrap(), 2);
+    assert_eq!(
+        r.zrem_range_by_score(key, f64::NEG_INFINITY, f64::INFINITY)
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(r.keys(key).await.unwrap().len(), 0);
+
+    r.set(key, "value").await.unwrap();
+    assert!(r.zadd(key, "m1", 1.0).await.is_err());
+    assert!(r.zcard(key).await.is_err());
+    assert!(r.del(key).await.unwrap());
+}
+
+/// `zrange_by_score_limit`, paging through a sorted set.
+pub async fn zset_limit<M: MemoryDB + ?Sized>(r: &M) {
+    let key = "_actix_cloud_zkey2";
+    let all = |offset, count| {
+        r.zrange_by_score_limit(key, f64::NEG_INFINITY, f64::INFINITY, offset, count)
+    };
+
+    let _ = r.del(key).await;
+
+    assert!(all(0, 10).await.unwrap().is_empty());
+    assert!(r.zadd(key, "m1", 1.0).await.unwrap());
+    assert!(r.zadd(key, "m3", 2.0).await.unwrap());
+    assert!(r.zadd(key, "m2", 2.0).await.unwrap());
+    assert!(r.zadd(key, "m4", f64::INFINITY).await.unwrap());
+    assert_eq!(
+        r.zrange_by_score_limit(key, 1.5, f64::INFINITY, 1, 2)
+            .await
+            .unwrap(),
+        [("m3".to_owned(), 2.0), ("m4".to_owned(), f64::INFINITY)]
+    );
+    assert_eq!(
+        all(0, 2).await.unwrap(),
+        [("m1".to_owned(), 1.0), ("m2".to_owned(), 2.0)]
+    );
+    assert_eq!(
+        all(3, 10).await.unwrap(),
+        [("m4".to_owned(), f64::INFINITY)]
+    );
+    assert_eq!(all(0, usize::MAX).await.unwrap().len(), 4);
+    assert!(all(4, 1).await.unwrap().is_empty());
+    assert!(all(0, 0).await.unwrap().is_empty());
+    assert!(r
+        .zrange_by_score_limit(key, 3.0, 1.0, 0, 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    r.set(key, "value").await.unwrap();
+    assert!(all(0, 10).await.is_err());
+    assert!(r.del(key).await.unwrap());
+}
+
+/// `publish` and `subscribe`.
+pub async fn pubsub<M: MemoryDB + ?Sized>(r: &M) {
+    let channel1 = "_actix_cloud_channel1";
+    let channel2 = "_actix_cloud_channel2";
+
+    assert_eq!(r.publish(channel1, "message").await.unwrap(), 0);
+
+    let mut s1 = r.subscribe(channel1).await.unwrap();
+    let mut s2 = r.subscribe("_actix_cl?ud_channel*").await.unwrap();
+    assert_eq!(r.publish(channel1, "message1").await.unwrap(), 2);
+    assert_eq!(r.publish(channel2, "message2").await.unwrap(), 1);
+
+    let msg = recv(&mut s1).await;
+    assert_eq!(msg.channel, channel1);
+    assert_eq!(msg.payload, "message1");
+    let msg = recv(&mut s2).await;
+    assert_eq!(msg.channel, channel1);
+    assert_eq!(msg.payload, "message1");
+    let msg = recv(&mut s2).await;
+    assert_eq!(msg.channel, channel2);
+    assert_eq!(msg.payload, "message2");
+
+    drop(s1);
+    drop(s2);
+    // Backends may unsubscribe in the background, wait until no subscriber is left.
+    timeout(Duration::from_secs(1), async {
+        while r.publish(channel1, "message").await.unwrap() != 0 {
+            yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+async fn recv<S: Stream + Unpin>(s: &mut S) -> S::Item {
+    timeout(Duration::from_secs(1), s.next())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+/// `flush`, which deletes all keys.
+pub async fn flush<M: MemoryDB + ?Sized>(r: &M) {
+    r.set("_actix_cloud_fkey1", "value").await.unwrap();
+    r.hset("_actix_cloud_fkey2", "field", "value")
+        .await
+        .unwrap();
+    r.set_ex("_actix_cloud_fkey3", "value", &Duration::from_secs(10))
+        .await
+        .unwrap();
+    r.flush().await.unwrap();
+    assert!(r.keys("*").await.unwrap().is_empty());
+    assert_eq!(r.scan("*", 10).await.unwrap().count().await, 0);
+    assert_eq!(r.dels_match("*").await.unwrap(), 0);
+    assert_eq!(r.get("_actix_cloud_fkey1").await.unwrap(), None);
+    assert!(r.hgetall("_actix_cloud_fkey2").await.unwrap().is_empty());
+}
+
+/// `watch`, for backends supporting keyspace notifications.
+pub async fn watch<M: MemoryDB + ?Sized>(r: &M, time: &Time) {
+    let key1 = "_actix_cloud_wkey1";
+    let key2 = "_actix_cloud_wkey2";
+    let event = |key: &str, kind| Event {
+        key: key.to_owned(),
+        kind,
+    };
+
+    let _ = r.dels(&[key1.to_owned(), key2.to_owned()]).await;
+
+    let mut s = r.watch("_actix_cl?ud_wkey*").await.unwrap();
+    r.set("_actix_cloud_other", "value").await.unwrap();
+    r.set(key1, "value").await.unwrap();
+    r.hset(key2, "field", "value").await.unwrap();
+    assert!(r.del(key1).await.unwrap());
+    assert!(!r.del(key1).await.unwrap());
+    assert!(r.del(key2).await.unwrap());
+    assert_eq!(recv(&mut s).await, event(key1, EventKind::Set));
+    assert_eq!(recv(&mut s).await, event(key2, EventKind::Set));
+    assert_eq!(recv(&mut s).await, event(key1, EventKind::Del));
+    assert_eq!(recv(&mut s).await, event(key2, EventKind::Del));
+
+    // Setting the value and TTL may generate separate events.
+    r.set_ex(key1, "value", &Duration::from_millis(100))
+        .await
+        .unwrap();
+    time.advance(Duration::from_millis(200)).await;
+    assert_eq!(r.get(key1).await.unwrap(), None);
+    loop {
+        let x = recv(&mut s).await;
+        assert_eq!(x.key, key1);
+        if x.kind == EventKind::Expired {
+            break;
+        }
+        assert_eq!(x.kind, EventKind::Set);
+    }
+
+    assert!(r.del("_actix_cloud_other").await.unwrap());
+}
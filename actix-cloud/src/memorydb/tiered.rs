@@ -0,0 +1,523 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use tokio::{task::AbortHandle, time::sleep};
+
+use super::{
+    clock::Clock,
+    default::{utf8, DefaultBackend, DefaultBackendBuilder, EvictionPolicy},
+    interface::{EventStream, KeyStream, MemoryDB, MessageStream, Reply, Transaction},
+};
+use crate::Result;
+
+/// Invalidation message prefix of a single key.
+const INVALIDATE_KEY: &str = "k";
+/// Invalidation message of all keys.
+const INVALIDATE_ALL: &str = "a";
+/// Interval to resubscribe after the invalidation channel is lost.
+const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Abort the invalidation task when the last clone of the backend is dropped.
+struct Listener(AbortHandle);
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Two-tier [`MemoryDB`]: a bounded local [`DefaultBackend`] (L1) in front of a remote backend (L2).
+///
+/// - Only string values are cached in L1, for at most `ttl`. Other operations go to L2 directly.
+/// - Writes go to L2, then the key is dropped from L1 of every instance through pub/sub on L2.
+/// - If the invalidation channel is lost, L1 is cleared and the channel is resubscribed.
+///
+/// L1 entries are not aware of the L2 TTL, an expired L2 key may be served from L1 for at most `ttl`.
+pub struct TieredBackend<M: ?Sized> {
+    l1: DefaultBackend,
+    l2: Arc<M>,
+    ttl: Duration,
+    channel: String,
+    /// Increased on every invalidation, used to detect invalidations racing with L1 fills.
+    version: Arc<AtomicU64>,
+    _listener: Arc<Listener>,
+}
+
+impl<M: ?Sized> Clone for TieredBackend<M> {
+    fn clone(&self) -> Self {
+        Self {
+            l1: self.l1.clone(),
+            l2: self.l2.clone(),
+            ttl: self.ttl,
+            channel: self.channel.clone(),
+            version: self.version.clone(),
+            _listener: self._listener.clone(),
+        }
+    }
+}
+
+impl<M: MemoryDB + ?Sized + 'static> TieredBackend<M> {
+    pub fn builder(l2: Arc<M>) -> TieredBackendBuilder<M> {
+        TieredBackendBuilder {
+            l2,
+            l1: DefaultBackend::builder()
+                .capacity(10000)
+                .eviction(EvictionPolicy::AllKeysLru),
+            ttl: Duration::from_secs(5),
+            channel: String::from("_actix_cloud_tiered"),
+        }
+    }
+
+    /// Local cache.
+    pub fn l1(&self) -> &DefaultBackend {
+        &self.l1
+    }
+
+    /// Remote backend.
+    pub fn l2(&self) -> &Arc<M> {
+        &self.l2
+    }
+
+    async fn listen(
+        l1: DefaultBackend,
+        l2: Arc<M>,
+        channel: String,
+        mut stream: MessageStream,
+        version: Arc<AtomicU64>,
+    ) {
+        loop {
+            while let Some(x) = stream.next().await {
+                version.fetch_add(1, Ordering::SeqCst);
+                if let Some(key) = x.payload.strip_prefix(INVALIDATE_KEY) {
+                    let _ = l1.del(key).await;
+                } else {
+                    let _ = l1.flush().await;
+                }
+            }
+            // Messages may be lost until resubscribed.
+            stream = loop {
+                sleep(RESUBSCRIBE_INTERVAL).await;
+                if let Ok(x) = l2.subscribe(&channel).await {
+                    break x;
+                }
+            };
+            version.fetch_add(1, Ordering::SeqCst);
+            let _ = l1.flush().await;
+        }
+    }
+
+    /// Cache `value` read from L2 at `version` in L1.
+    async fn fill(&self, key: &str, value: &[u8], version: u64) {
+        let _ = self.l1.set_ex_bytes(key, value, &self.ttl).await;
+        // Invalidated while reading from L2, the value may be stale.
+        if self.version.load(Ordering::SeqCst) != version {
+            let _ = self.l1.del(key).await;
+        }
+    }
+
+    /// Drop `key` from L1 of all instances.
+    async fn invalidate(&self, key: &str) -> Result<()> {
+        self.version.fetch_add(1, Ordering::SeqCst);
+        self.l1.del(key).await?;
+        self.l2
+            .publish(&self.channel, &format!("{INVALIDATE_KEY}{key}"))
+            .await?;
+        Ok(())
+    }
+
+    /// Clear L1 of all instances.
+    async fn invalidate_all(&self) -> Result<()> {
+        self.version.fetch_add(1, Ordering::SeqCst);
+        self.l1.flush().await?;
+        self.l2.publish(&self.channel, INVALIDATE_ALL).await?;
+        Ok(())
+    }
+}
+
+pub struct TieredBackendBuilder<M: ?Sized> {
+    l2: Arc<M>,
+    l1: DefaultBackendBuilder,
+    ttl: Duration,
+    channel: String,
+}
+
+impl<M: MemoryDB + ?Sized + 'static> TieredBackendBuilder<M> {
+    /// Maximum number of keys in L1. Default is 10000, least recently used keys are evicted.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.l1 = self.l1.capacity(capacity);
+        self
+    }
+
+    /// Time source used to expire L1 entries. Default is [`SystemClock`](super::clock::SystemClock).
+    ///
+    /// Use [`MockClock`](super::clock::MockClock) to control time in tests.
+    pub fn clock<C>(mut self, clock: C) -> Self
+    where
+        C: Clock + 'static,
+    {
+        self.l1 = self.l1.clock(clock);
+        self
+    }
+
+    /// TTL of L1 entries. Default is 5 seconds.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Pub/sub channel on L2 for invalidation, shared by all instances.
+    /// Glob-style metacharacters are not allowed.
+    pub fn channel<S>(mut self, channel: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.channel = channel.into();
+        self
+    }
+
+    /// # Panics
+    /// Panics if it is not called inside a tokio runtime.
+    pub async fn build(self) -> Result<TieredBackend<M>> {
+        let l1 = self.l1.build()?;
+        let version = Arc::new(AtomicU64::new(0));
+        let stream = self.l2.subscribe(&self.channel).await?;
+        let task = tokio::spawn(TieredBackend::listen(
+            l1.clone(),
+            self.l2.clone(),
+            self.channel.clone(),
+            stream,
+            version.clone(),
+        ));
+        Ok(TieredBackend {
+            l1,
+            l2: self.l2,
+            ttl: self.ttl,
+            channel: self.channel,
+            version,
+            _listener: Arc::new(Listener(task.abort_handle())),
+        })
+    }
+}
+
+#[async_trait]
+impl<M: MemoryDB + ?Sized + 'static> MemoryDB for TieredBackend<M> {
+    async fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.l2.set(key, value).await?;
+        self.invalidate(key).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        self.get_bytes(key).await?.map(utf8).transpose()
+    }
+
+    async fn get_del(&self, key: &str) -> Result<Option<String>> {
+        let ret = self.l2.get_del(key).await?;
+        self.invalidate(key).await?;
+        Ok(ret)
+    }
+
+    async fn get_ex(&self, key: &str, ttl: &Duration) -> Result<Option<String>> {
+        let ret = self.l2.get_ex(key, ttl).await?;
+        if ret.is_some() {
+            self.invalidate(key).await?;
+        }
+        Ok(ret)
+    }
+
+    async fn set_ex(&self, key: &str, value: &str, ttl: &Duration) -> Result<()> {
+        self.l2.set_ex(key, value, ttl).await?;
+        self.invalidate(key).await
+    }
+
+    async fn set_bytes(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.l2.set_bytes(key, value).await?;
+        self.invalidate(key).await
+    }
+
+    async fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if let Ok(Some(x)) = self.l1.get_bytes(key).await {
+            return Ok(Some(x));
+        }
+        let version = self.version.load(Ordering::SeqCst);
+        let ret = self.l2.get_bytes(key).await?;
+        if let Some(x) = &ret {
+            self.fill(key, x, version).await;
+        }
+        Ok(ret)
+    }
+
+    async fn get_del_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let ret = self.l2.get_del_bytes(key).await?;
+        self.invalidate(key).await?;
+        Ok(ret)
+    }
+
+    async fn get_ex_bytes(&self, key: &str, ttl: &Duration) -> Result<Option<Vec<u8>>> {
+        let ret = self.l2.get_ex_bytes(key, ttl).await?;
+        if ret.is_some() {
+            self.invalidate(key).await?;
+        }
+        Ok(ret)
+    }
+
+    async fn set_ex_bytes(&self, key: &str, value: &[u8], ttl: &Duration) -> Result<()> {
+        self.l2.set_ex_bytes(key, value, ttl).await?;
+        self.invalidate(key).await
+    }
+
+    async fn set_nx(&self, key: &str, value: &str) -> Result<bool> {
+        let ret = self.l2.set_nx(key, value).await?;
+        if ret {
+            self.invalidate(key).await?;
+        }
+        Ok(ret)
+    }
+
+    async fn set_ex_nx(&self, key: &str, value: &str, ttl: &Duration) -> Result<bool> {
+        let ret = self.l2.set_ex_nx(key, value, ttl).await?;
+        if ret {
+            self.invalidate(key).await?;
+        }
+        Ok(ret)
+    }
+
+    async fn del(&self, key: &str) -> Result<bool> {
+        let ret = self.l2.del(key).await?;
+        self.invalidate(key).await?;
+        Ok(ret)
+    }
+
+    async fn del_if_eq(&self, key: &str, value: &str) -> Result<bool> {
+        let ret = self.l2.del_if_eq(key, value).await?;
+        if ret {
+            self.invalidate(key).await?;
+        }
+        Ok(ret)
+    }
+
+    async fn expire(&self, key: &str, ttl: i64) -> Result<bool> {
+        let ret = self.l2.expire(key, ttl).await?;
+        // A shorter TTL in L2 must not be outlived by the L1 copy.
+        if ret {
+            self.invalidate(key).await?;
+        }
+        Ok(ret)
+    }
+
+    async fn expire_if_eq(&self, key: &str, value: &str, ttl: &Duration) -> Result<bool> {
+        let ret = self.l2.expire_if_eq(key, value, ttl).await?;
+        if ret {
+            self.invalidate(key).await?;
+        }
+        Ok(ret)
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.l2.flush().await?;
+        self.invalidate_all().await
+    }
+
+    async fn keys(&self, key: &str) -> Result<Vec<String>> {
+        self.l2.keys(key).await
+    }
+
+    async fn scan(&self, pattern: &str, count: usize) -> Result<KeyStream> {
+        self.l2.scan(pattern, count).await
+    }
+
+    async fn dels(&self, keys: &[String]) -> Result<u64> {
+        let ret = self.l2.dels(keys).await?;
+        for i in keys {
+            self.invalidate(i).await?;
+        }
+        Ok(ret)
+    }
+
+    async fn mget(&self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        let mut ret = Vec::with_capacity(keys.len());
+        let mut miss = Vec::new();
+        for (i, k) in keys.iter().enumerate() {
+            match self.l1.get(k).await {
+                Ok(Some(x)) => ret.push(Some(x)),
+                _ => {
+                    ret.push(None);
+                    miss.push(i);
+                }
+            }
+        }
+        if miss.is_empty() {
+            return Ok(ret);
+        }
+        let version = self.version.load(Ordering::SeqCst);
+        let values = self
+            .l2
+            .mget(&miss.iter().map(|&i| keys[i].clone()).collect::<Vec<_>>())
+            .await?;
+        for (i, v) in miss.into_iter().zip(values) {
+            if let Some(x) = &v {
+                self.fill(&keys[i], x.as_bytes(), version).await;
+            }
+            ret[i] = v;
+        }
+        Ok(ret)
+    }
+
+    async fn exec(&self, tx: &Transaction) -> Result<Vec<Reply>> {
+        let ret = self.l2.exec(tx).await;
+        for i in tx.ops() {
+            self.invalidate(i.key()).await?;
+        }
+        ret
+    }
+
+    async fn dels_match(&self, pattern: &str) -> Result<u64> {
+        let ret = self.l2.dels_match(pattern).await?;
+        self.invalidate_all().await?;
+        Ok(ret)
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<i64>> {
+        self.l2.ttl(key).await
+    }
+
+    async fn pttl(&self, key: &str) -> Result<Option<i64>> {
+        self.l2.pttl(key).await
+    }
+
+    async fn incr_by(&self, key: &str, delta: i64, ttl: Option<&Duration>) -> Result<i64> {
+        let ret = self.l2.incr_by(key, delta, ttl).await?;
+        self.invalidate(key).await?;
+        Ok(ret)
+    }
+
+    async fn decr_by(&self, key: &str, delta: i64, ttl: Option<&Duration>) -> Result<i64> {
+        let ret = self.l2.decr_by(key, delta, ttl).await?;
+        self.invalidate(key).await?;
+        Ok(ret)
+    }
+
+    async fn hset(&self, key: &str, field: &str, value: &str) -> Result<bool> {
+        self.l2.hset(key, field, value).await
+    }
+
+    async fn hget(&self, key: &str, field: &str) -> Result<Option<String>> {
+        self.l2.hget(key, field).await
+    }
+
+    async fn hdel(&self, key: &str, field: &str) -> Result<bool> {
+        self.l2.hdel(key, field).await
+    }
+
+    async fn hgetall(&self, key: &str) -> Result<HashMap<String, String>> {
+        self.l2.hgetall(key).await
+    }
+
+    async fn hincr(&self, key: &str, field: &str, delta: i64) -> Result<i64> {
+        self.l2.hincr(key, field, delta).await
+    }
+
+    async fn zadd(&self, key: &str, member: &str, score: f64) -> Result<bool> {
+        self.l2.zadd(key, member, score).await
+    }
+
+    async fn zrem(&self, key: &str, member: &str) -> Result<bool> {
+        self.l2.zrem(key, member).await
+    }
+
+    async fn zrange_by_score(&self, key: &str, min: f64, max: f64) -> Result<Vec<(String, f64)>> {
+        self.l2.zrange_by_score(key, min, max).await
+    }
+
+    async fn zrange_by_score_limit(
+        &self,
+        key: &str,
+        min: f64,
+        max: f64,
+        offset: usize,
+        count: usize,
+    ) -> Result<Vec<(String, f64)>> {
+        self.l2
+            .zrange_by_score_limit(key, min, max, offset, count)
+            .await
+    }
+
+    async fn zrem_range_by_score(&self, key: &str, min: f64, max: f64) -> Result<u64> {
+        self.l2.zrem_range_by_score(key, min, max).await
+    }
+
+    async fn zcard(&self, key: &str) -> Result<u64> {
+        self.l2.zcard(key).await
+    }
+
+    async fn publish(&self, channel: &str, message: &str) -> Result<u64> {
+        self.l2.publish(channel, message).await
+    }
+
+    async fn subscribe(&self, pattern: &str) -> Result<MessageStream> {
+        self.l2.subscribe(pattern).await
+    }
+
+    async fn watch(&self, pattern: &str) -> Result<EventStream> {
+        self.l2.watch(pattern).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memorydb::clock::MockClock;
+
+    // Paused time makes each `sleep` return once the listeners are idle.
+    #[tokio::test(start_paused = true)]
+    async fn test_tiered() {
+        let clock = MockClock::new();
+        let l2 = Arc::new(DefaultBackend::new(None));
+        let t1 = TieredBackend::builder(l2.clone())
+            .ttl(Duration::from_millis(500))
+            .clock(clock.clone())
+            .build()
+            .await
+            .unwrap();
+        let t2 = TieredBackend::builder(l2.clone()).build().await.unwrap();
+        let key = "_actix_cloud_tkey1";
+
+        t1.set(key, "value1").await.unwrap();
+        assert_eq!(t1.get(key).await.unwrap().unwrap(), "value1");
+        assert_eq!(t1.l1().get(key).await.unwrap().unwrap(), "value1");
+
+        // Written behind the cache, L1 is stale until its TTL.
+        l2.set(key, "value2").await.unwrap();
+        assert_eq!(t1.get(key).await.unwrap().unwrap(), "value1");
+        clock.advance(Duration::from_millis(600));
+        assert_eq!(t1.get(key).await.unwrap().unwrap(), "value2");
+
+        // Changing the TTL invalidates L1, so the copy never outlives the key.
+        assert!(t1.expire(key, 100).await.unwrap());
+        assert_eq!(t1.l1().get(key).await.unwrap(), None);
+
+        // Written by another instance, L1 is invalidated.
+        assert_eq!(t2.get(key).await.unwrap().unwrap(), "value2");
+        t1.set(key, "value3").await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(t2.l1().get(key).await.unwrap(), None);
+        assert_eq!(t2.get(key).await.unwrap().unwrap(), "value3");
+
+        t2.flush().await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(t1.l1().stats().keys, 0);
+        assert_eq!(t1.get(key).await.unwrap(), None);
+
+        let weak = Arc::downgrade(&l2);
+        drop((t1, t2, l2));
+        sleep(Duration::from_millis(50)).await;
+        assert!(weak.upgrade().is_none());
+    }
+}
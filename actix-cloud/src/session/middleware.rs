@@ -1,6 +1,5 @@
 use std::{borrow::Cow, collections::HashMap, fmt, future::Future, pin::Pin, rc::Rc, sync::Arc};
 
-use actix_utils::future::{ready, Ready};
 use actix_web::{
     body::MessageBody,
     cookie::{time::Duration, Cookie, CookieJar, Key},
@@ -8,6 +7,7 @@ use actix_web::{
     http::header::{HeaderValue, SET_COOKIE},
     HttpResponse,
 };
+use std::future::{ready, Ready};
 
 use super::{
     config::{
@@ -5,13 +5,13 @@ use std::{
     rc::Rc,
 };
 
-use actix_utils::future::{ready, Ready};
 use actix_web::{
     dev::{Extensions, Payload, ServiceRequest, ServiceResponse},
     error::Error,
     FromRequest, HttpMessage, HttpRequest,
 };
 use serde::{de::DeserializeOwned, Serialize};
+use std::future::{ready, Ready};
 
 use crate::Result;
 
@@ -4,7 +4,13 @@ use std::{collections::HashMap, sync::Arc};
 use actix_web::cookie::time::Duration;
 
 use super::{utils::generate_session_key, SessionKey};
-use crate::{memorydb::MemoryDB, Result};
+use crate::{
+    memorydb::{
+        ext::{Codec as _, Json, MemoryDBExt as _},
+        MemoryDB,
+    },
+    Result,
+};
 
 pub(crate) type SessionState = HashMap<String, String>;
 
@@ -45,12 +51,10 @@ impl SessionStore {
 
     pub async fn load(&self, session_key: &SessionKey) -> Result<Option<SessionState>> {
         let cache_key = (self.configuration.cache_keygen)(session_key.as_ref());
-        let value = self.client.get(&cache_key).await?;
+        let value = self.client.get_bytes(&cache_key).await?;
 
-        match value {
-            None => Ok(None),
-            Some(value) => Ok(serde_json::from_str(&value).ok()),
-        }
+        // Invalid state is treated as missing.
+        Ok(value.and_then(|x| Json::decode(&x).ok()))
     }
 
     pub async fn save(
@@ -59,12 +63,11 @@ impl SessionStore {
         id: &Option<String>,
         ttl: &Duration,
     ) -> Result<SessionKey> {
-        let body = serde_json::to_string(&session_state)?;
         let session_key = generate_session_key();
         let cache_key = (self.configuration.cache_keygen)(session_key.as_ref());
 
         self.client
-            .set_ex(&cache_key, &body, &Self::parse_ttl(ttl))
+            .set_ex_json(&cache_key, &session_state, &Self::parse_ttl(ttl))
             .await?;
 
         if let Some(id) = id {
@@ -83,11 +86,10 @@ impl SessionStore {
         id: &Option<String>,
         ttl: &Duration,
     ) -> Result<SessionKey> {
-        let body = serde_json::to_string(&session_state)?;
         let cache_key = (self.configuration.cache_keygen)(session_key.as_ref());
 
         self.client
-            .set_ex(&cache_key, &body, &Self::parse_ttl(ttl))
+            .set_ex_json(&cache_key, &session_state, &Self::parse_ttl(ttl))
             .await?;
 
         if let Some(id) = id {
@@ -134,3 +136,35 @@ impl SessionStore {
         time::Duration::from_secs(t)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memorydb::{clock::MockClock, default::DefaultBackend};
+
+    #[tokio::test]
+    async fn test_ttl() {
+        let clock = MockClock::new();
+        let db = DefaultBackend::builder()
+            .clock(clock.clone())
+            .build()
+            .unwrap();
+        let store = SessionStore::new(Arc::new(db));
+        let state = HashMap::from([("key".to_owned(), "value".to_owned())]);
+        let id = Some("user".to_owned());
+
+        let key = store
+            .save(state.clone(), &id, &Duration::seconds(60))
+            .await
+            .unwrap();
+        clock.advance(time::Duration::from_secs(30));
+        store
+            .update_ttl(&key, &id, &Duration::seconds(60))
+            .await
+            .unwrap();
+        clock.advance(time::Duration::from_secs(59));
+        assert_eq!(store.load(&key).await.unwrap().unwrap(), state);
+        clock.advance(time::Duration::from_secs(1));
+        assert_eq!(store.load(&key).await.unwrap(), None);
+    }
+}
@@ -29,9 +29,12 @@ impl GlobalState {
     }
 }
 
+type StopHook = Box<dyn FnOnce() + Send>;
+
 #[derive(Default)]
 pub struct ServerHandle {
     inner: Mutex<Option<dev::ServerHandle>>,
+    hooks: Mutex<Vec<StopHook>>,
 
     /// Whether server is running (never received stop signals).
     pub running: RwLock<bool>,
@@ -48,14 +51,33 @@ impl ServerHandle {
         *self.running.write() = true;
         *self.start_time.write() = Utc::now();
 
-        server.await.map_err(Into::into)
+        let ret = server.await.map_err(Into::into);
+        // The server may also be stopped by signals.
+        self.run_hooks();
+        ret
     }
 
     /// Sends stop signal through contained server handle.
     pub fn stop(&self, graceful: bool) {
         *self.running.write() = false;
         *self.stop_time.write() = Some(Utc::now());
+        self.run_hooks();
         #[allow(clippy::let_underscore_future)]
         let _ = self.inner.lock().as_ref().unwrap().stop(graceful);
     }
+
+    /// Call `f` once when the server is stopped, e.g. to stop background workers.
+    pub fn on_stop<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.hooks.lock().push(Box::new(f));
+    }
+
+    fn run_hooks(&self) {
+        let hooks = std::mem::take(&mut *self.hooks.lock());
+        for i in hooks {
+            i();
+        }
+    }
 }